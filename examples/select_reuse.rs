@@ -0,0 +1,27 @@
+extern crate kuchiki;
+
+use kuchiki::traits::*;
+use std::time::Instant;
+
+fn main() {
+    let html = ::std::iter::repeat("<ul><li class=a>1</li><li>2</li><li class=a>3</li></ul>")
+        .take(100).collect::<String>();
+    let document = kuchiki::parse_html().one(html);
+    let selector = "ul > li.a:not(:first-child), li + li";
+    let iterations = 1000;
+
+    let start = Instant::now();
+    let mut count = 0;
+    for _ in 0..iterations {
+        count += document.select(selector).unwrap().count();
+    }
+    println!("Compiling each time: {:?} ({} matches)", start.elapsed(), count);
+
+    let start = Instant::now();
+    let selectors = kuchiki::Selectors::compile(selector).unwrap();
+    let mut count = 0;
+    for _ in 0..iterations {
+        count += document.select_with(&selectors).count();
+    }
+    println!("Compiling once:      {:?} ({} matches)", start.elapsed(), count);
+}
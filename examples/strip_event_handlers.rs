@@ -0,0 +1,20 @@
+extern crate kuchiki;
+
+use kuchiki::traits::*;
+use std::rc::Rc;
+
+fn main() {
+    let html = r#"
+        <p onclick="track()">Hello, <a href="/world" onmouseover="track()">world</a>!</p>
+    "#;
+    let document = kuchiki::parse_html().one(html);
+
+    let mut opts = kuchiki::SerializeOpts::default();
+    opts.attribute_filter = Some(Rc::new(|_element, attribute| {
+        !attribute.local.starts_with("on")
+    }));
+
+    // The tree is not modified, only the output.
+    let body = document.select_first("body").unwrap().unwrap();
+    println!("{}", body.as_node().to_string_with_options(&opts));
+}
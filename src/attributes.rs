@@ -1,41 +1,150 @@
 use string_cache::{QualName, Atom};
-use std::collections::hash_map::{self, HashMap};
+use std::cell::Ref;
+use std::iter::FromIterator;
+use std::ops::Deref;
+use std::slice;
 
-/// Convenience wrapper around a hashmap that adds method for attributes in the null namespace.
-#[derive(Debug, PartialEq, Clone)]
+/// Convenience wrapper around a list of attributes
+/// that adds method for attributes in the null namespace.
+///
+/// Attributes are kept in insertion order, which for parsed elements is source order,
+/// so that serialization is deterministic.
+/// Lookups are linear, which is fast for the small number of attributes elements typically have.
+#[derive(Debug, Clone)]
 pub struct Attributes {
-    /// A map of attributes whose name can have namespaces.
-    pub map: HashMap<QualName, String>,
+    /// A list of attributes whose name can have namespaces.
+    ///
+    /// Each name should occur at most once.
+    pub list: Vec<(QualName, String)>,
 }
 
 impl Attributes {
-    /// Like HashMap::contains
+    fn position(&self, name: &QualName) -> Option<usize> {
+        self.list.iter().position(|&(ref key, _)| key == name)
+    }
+
+    /// Return whether there is an attribute with the given local name.
     pub fn contains<A: Into<Atom>>(&self, local_name: A) -> bool {
-        self.map.contains_key(&QualName::new(ns!(), local_name.into()))
+        self.position(&QualName::new(ns!(), local_name.into())).is_some()
     }
 
-    /// Like HashMap::get
+    /// Return the value of the attribute with the given local name, if any.
     pub fn get<A: Into<Atom>>(&self, local_name: A) -> Option<&str> {
-        self.map.get(&QualName::new(ns!(), local_name.into())).map(AsRef::as_ref)
+        self.position(&QualName::new(ns!(), local_name.into())).map(|i| &*self.list[i].1)
     }
 
-    /// Like HashMap::get_mut
+    /// Return a mutable reference to the value of the attribute with the given local name,
+    /// if any.
     pub fn get_mut<A: Into<Atom>>(&mut self, local_name: A) -> Option<&mut String> {
-        self.map.get_mut(&QualName::new(ns!(), local_name.into()))
-    }
-
-    /// Like HashMap::entry
-    pub fn entry<A: Into<Atom>>(&mut self, local_name: A) -> hash_map::Entry<QualName, String> {
-        self.map.entry(QualName::new(ns!(), local_name.into()))
+        match self.position(&QualName::new(ns!(), local_name.into())) {
+            Some(i) => Some(&mut self.list[i].1),
+            None => None,
+        }
     }
 
-    /// Like HashMap::insert
+    /// Set the value of the attribute with the given local name, and return the old value.
+    ///
+    /// An existing attribute keeps its position, a new one is added at the end.
     pub fn insert<A: Into<Atom>>(&mut self, local_name: A, value: String) -> Option<String> {
-        self.map.insert(QualName::new(ns!(), local_name.into()), value)
+        self.insert_qualified(QualName::new(ns!(), local_name.into()), value)
     }
 
-    /// Like HashMap::remove
+    /// Remove the attribute with the given local name and return its value, if any.
     pub fn remove<A: Into<Atom>>(&mut self, local_name: A) -> Option<String> {
-        self.map.remove(&QualName::new(ns!(), local_name.into()))
+        match self.position(&QualName::new(ns!(), local_name.into())) {
+            Some(i) => Some(self.list.remove(i).1),
+            None => None,
+        }
+    }
+
+    fn insert_qualified(&mut self, name: QualName, value: String) -> Option<String> {
+        match self.position(&name) {
+            Some(i) => Some(::std::mem::replace(&mut self.list[i].1, value)),
+            None => {
+                self.list.push((name, value));
+                None
+            }
+        }
+    }
+}
+
+/// Attribute order is not significant for equality.
+impl PartialEq for Attributes {
+    fn eq(&self, other: &Attributes) -> bool {
+        self.list.len() == other.list.len() &&
+        self.list.iter().all(|&(ref name, ref value)| {
+            other.position(name).map_or(false, |i| other.list[i].1 == *value)
+        })
+    }
+}
+
+/// Like `insert`, an attribute that is already present keeps its position
+/// and has its value replaced.
+impl Extend<(QualName, String)> for Attributes {
+    fn extend<I: IntoIterator<Item=(QualName, String)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.insert_qualified(name, value);
+        }
+    }
+}
+
+impl FromIterator<(QualName, String)> for Attributes {
+    fn from_iter<I: IntoIterator<Item=(QualName, String)>>(iter: I) -> Attributes {
+        let mut attributes = Attributes { list: Vec::new() };
+        attributes.extend(iter);
+        attributes
+    }
+}
+
+/// A borrowed view of an element’s attributes, returned by `ElementData::attributes_iter`.
+///
+/// This holds a borrow of the attributes’ `RefCell`:
+/// they can not be modified until it is dropped.
+pub struct AttributesRef<'a>(pub Ref<'a, Attributes>);
+
+impl<'a> AttributesRef<'a> {
+    /// Iterate `(local_name, value)` pairs of attributes in the null namespace, in order.
+    #[inline]
+    pub fn iter<'b>(&'b self) -> AttributesIter<'b> {
+        AttributesIter(self.0.list.iter())
+    }
+
+    /// Iterate all attributes with their namespaced name, in order.
+    #[inline]
+    pub fn iter_qualified<'b>(&'b self) -> slice::Iter<'b, (QualName, String)> {
+        self.0.list.iter()
+    }
+}
+
+impl<'a> Deref for AttributesRef<'a> {
+    type Target = Attributes;
+    #[inline]
+    fn deref(&self) -> &Attributes {
+        &self.0
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b AttributesRef<'a> {
+    type Item = (&'b str, &'b str);
+    type IntoIter = AttributesIter<'b>;
+    #[inline]
+    fn into_iter(self) -> AttributesIter<'b> {
+        self.iter()
+    }
+}
+
+/// An iterator of `(local_name, value)` pairs of attributes in the null namespace.
+pub struct AttributesIter<'b>(slice::Iter<'b, (QualName, String)>);
+
+impl<'b> Iterator for AttributesIter<'b> {
+    type Item = (&'b str, &'b str);
+    #[inline]
+    fn next(&mut self) -> Option<(&'b str, &'b str)> {
+        for &(ref name, ref value) in self.0.by_ref() {
+            if name.ns == ns!() {
+                return Some((&*name.local, &**value))
+            }
+        }
+        None
     }
 }
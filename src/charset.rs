@@ -0,0 +1,196 @@
+use html5ever::encoding::EncodingRef;
+use html5ever::encoding::all::{UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use html5ever::encoding::label::encoding_from_whatwg_label;
+use std::ascii::AsciiExt;
+
+/// Determine the character encoding of an HTML document,
+/// and return it with the length of the byte order mark to skip.
+///
+/// This follows the HTML specification’s encoding sniffing algorithm, in order:
+/// a byte order mark, then the charset declared by the transport layer
+/// (for example in a `Content-Type` header),
+/// then a `<meta>` tag in the first 1024 bytes, then windows-1252.
+pub fn detect(bytes: &[u8], declared_charset: Option<&str>) -> (EncodingRef, usize) {
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        return (UTF_8, 3)
+    }
+    if bytes.starts_with(b"\xFE\xFF") {
+        return (UTF_16BE, 2)
+    }
+    if bytes.starts_with(b"\xFF\xFE") {
+        return (UTF_16LE, 2)
+    }
+    if let Some(encoding) = declared_charset.and_then(encoding_from_whatwg_label) {
+        return (encoding, 0)
+    }
+    let prescan_length = ::std::cmp::min(bytes.len(), 1024);
+    match prescan(&bytes[..prescan_length]) {
+        Some(encoding) => (encoding, 0),
+        None => (WINDOWS_1252, 0)
+    }
+}
+
+/// A simplified version of the specification’s “prescan a byte stream” algorithm.
+fn prescan(bytes: &[u8]) -> Option<EncodingRef> {
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if rest.starts_with(b"<!--") {
+            i += match find(&rest[4..], b"-->") {
+                Some(end) => 4 + end + 3,
+                None => return None
+            };
+        } else if rest.len() > 6 && rest[..5].eq_ignore_ascii_case(b"<meta") &&
+                  (is_space(rest[5]) || rest[5] == b'/') {
+            i += 5;
+            let mut charset = None;
+            let mut http_equiv_content_type = false;
+            let mut content_charset = None;
+            while let Some((name, value)) = next_attribute(bytes, &mut i) {
+                match &*name {
+                    b"charset" => if charset.is_none() {
+                        charset = encoding_from_whatwg_label(&String::from_utf8_lossy(&value))
+                    },
+                    b"http-equiv" => {
+                        http_equiv_content_type = value.eq_ignore_ascii_case(b"content-type")
+                    }
+                    b"content" => if content_charset.is_none() {
+                        content_charset = charset_from_content(&value)
+                            .and_then(|label| encoding_from_whatwg_label(&label))
+                    },
+                    _ => {}
+                }
+            }
+            let encoding = if charset.is_some() {
+                charset
+            } else if http_equiv_content_type {
+                content_charset
+            } else {
+                None
+            };
+            if let Some(encoding) = encoding {
+                // A document that was decoded enough to find this tag is not UTF-16.
+                return match encoding.whatwg_name() {
+                    Some("utf-16be") | Some("utf-16le") => Some(UTF_8),
+                    Some("x-user-defined") => Some(WINDOWS_1252),
+                    _ => Some(encoding)
+                }
+            }
+        } else if rest.len() > 1 && rest[0] == b'<' &&
+                  (is_ascii_alpha(rest[1]) || (rest[1] == b'/' && rest.len() > 2 &&
+                                               is_ascii_alpha(rest[2]))) {
+            // Skip the tag name, then its attributes.
+            while i < bytes.len() && !is_space(bytes[i]) && bytes[i] != b'>' {
+                i += 1
+            }
+            while next_attribute(bytes, &mut i).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            i += match find(rest, b">") {
+                Some(end) => end + 1,
+                None => return None
+            };
+        } else {
+            i += 1
+        }
+    }
+    None
+}
+
+/// The specification’s “get an attribute” algorithm.
+/// Names are lowercased, values are ASCII-lowercased.
+/// Return `None` at the end of the tag, and then leave `i` after the `>`.
+fn next_attribute(bytes: &[u8], i: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    while *i < bytes.len() && (is_space(bytes[*i]) || bytes[*i] == b'/') {
+        *i += 1
+    }
+    if *i >= bytes.len() || bytes[*i] == b'>' {
+        *i += 1;
+        return None
+    }
+    let mut name = Vec::new();
+    while *i < bytes.len() {
+        let byte = bytes[*i];
+        if byte == b'=' && !name.is_empty() {
+            break
+        }
+        if is_space(byte) || byte == b'/' || byte == b'>' {
+            break
+        }
+        name.push(byte.to_ascii_lowercase());
+        *i += 1
+    }
+    while *i < bytes.len() && is_space(bytes[*i]) {
+        *i += 1
+    }
+    if *i >= bytes.len() || bytes[*i] != b'=' {
+        return Some((name, Vec::new()))
+    }
+    *i += 1;
+    while *i < bytes.len() && is_space(bytes[*i]) {
+        *i += 1
+    }
+    let mut value = Vec::new();
+    if *i < bytes.len() && (bytes[*i] == b'"' || bytes[*i] == b'\'') {
+        let quote = bytes[*i];
+        *i += 1;
+        while *i < bytes.len() && bytes[*i] != quote {
+            value.push(bytes[*i].to_ascii_lowercase());
+            *i += 1
+        }
+        *i += 1;
+    } else {
+        while *i < bytes.len() && !is_space(bytes[*i]) && bytes[*i] != b'>' {
+            value.push(bytes[*i].to_ascii_lowercase());
+            *i += 1
+        }
+    }
+    Some((name, value))
+}
+
+/// The specification’s “extract a character encoding from a meta element” algorithm.
+fn charset_from_content(content: &[u8]) -> Option<String> {
+    let mut rest = content;
+    loop {
+        let start = match find(rest, b"charset") {
+            Some(start) => start,
+            None => return None
+        };
+        rest = skip_spaces(&rest[start + "charset".len()..]);
+        if rest.first() == Some(&b'=') {
+            rest = skip_spaces(&rest[1..]);
+            break
+        }
+    }
+    let value = match rest.first() {
+        Some(&quote) if quote == b'"' || quote == b'\'' => {
+            match rest[1..].iter().position(|&byte| byte == quote) {
+                Some(end) => &rest[1..1 + end],
+                None => return None
+            }
+        }
+        Some(_) => {
+            let end = rest.iter().position(|&byte| is_space(byte) || byte == b';')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        }
+        None => return None
+    };
+    Some(String::from_utf8_lossy(value).into_owned())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn skip_spaces(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&byte| !is_space(byte)).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn is_ascii_alpha(byte: u8) -> bool {
+    matches!(byte, b'a'...b'z' | b'A'...b'Z')
+}
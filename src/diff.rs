@@ -0,0 +1,210 @@
+use string_cache::QualName;
+
+use iter::NodeIterator;
+use select::css_path_step;
+use tree::{NodeRef, NodeData};
+
+/// A difference between two trees, as found by `NodeRef::diff`.
+///
+/// Paths are like those of `NodeRef::css_path`, without `#id` steps,
+/// and are relative to the root of the tree that the node is in.
+/// They are empty for nodes without an element ancestor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffOp {
+    /// A node of the other tree that is not in this tree:
+    /// the child at `index` of the element at `path` in the other tree.
+    Insert {
+        /// The path of the parent element, in the other tree.
+        path: String,
+        /// The index of the node among all the children of its parent.
+        index: usize,
+        /// The node in the other tree.
+        node: NodeRef,
+    },
+
+    /// A node of this tree that is not in the other tree:
+    /// the child at `index` of the element at `path` in this tree.
+    Remove {
+        /// The path of the parent element, in this tree.
+        path: String,
+        /// The index of the node among all the children of its parent.
+        index: usize,
+        /// The node in this tree.
+        node: NodeRef,
+    },
+
+    /// An attribute of the element at `path` in this tree
+    /// was added (`old` is `None`), removed (`new` is `None`), or changed.
+    Attribute {
+        /// The path of the element, in this tree.
+        path: String,
+        /// The name of the attribute.
+        name: QualName,
+        /// The value in this tree.
+        old: Option<String>,
+        /// The value in the other tree.
+        new: Option<String>,
+    },
+
+    /// The contents of a text or comment node changed:
+    /// the child at `index` of the element at `path` in this tree.
+    Text {
+        /// The path of the parent element, in this tree.
+        path: String,
+        /// The index of the node among all the children of its parent.
+        index: usize,
+        /// The contents in this tree.
+        old: String,
+        /// The contents in the other tree.
+        new: String,
+    },
+}
+
+impl NodeRef {
+    /// Compare this subtree with another one, and return their differences.
+    ///
+    /// This is a simple top-down comparison rather than a minimal edit script.
+    /// Nodes are matched with the node at the same position in the other tree
+    /// if they have the same type (and name, for elements).
+    /// A mismatched child is reported as an insertion (or removal)
+    /// if the next child on the other side matches instead,
+    /// or as a removal followed by an insertion otherwise.
+    /// Attribute order is not significant. Template contents are not compared.
+    ///
+    /// Differences are listed in tree order of the matched nodes they are found in,
+    /// so the result is deterministic. An empty result means the trees are equal,
+    /// like `deep_eq` does (except for template contents).
+    pub fn diff(&self, other: &NodeRef) -> Vec<DiffOp> {
+        let mut ops = Vec::new();
+        if !same_kind(self, other) {
+            ops.push(remove(self));
+            ops.push(insert(other));
+            return ops
+        }
+        // Use an explicit stack rather than recursion,
+        // for the same reason as in `impl Drop for Node`.
+        let mut stack = vec![(self.clone(), other.clone())];
+        while let Some((a, b)) = stack.pop() {
+            compare_contents(&a, &b, &mut ops);
+            let a_children = a.children().collect::<Vec<_>>();
+            let b_children = b.children().collect::<Vec<_>>();
+            let mut matched = Vec::new();
+            let (mut i, mut j) = (0, 0);
+            while i < a_children.len() && j < b_children.len() {
+                let (a_child, b_child) = (&a_children[i], &b_children[j]);
+                if same_kind(a_child, b_child) {
+                    matched.push((a_child.clone(), b_child.clone()));
+                    i += 1;
+                    j += 1;
+                } else if b_children.get(j + 1).map_or(false, |next| same_kind(a_child, next)) {
+                    ops.push(insert(b_child));
+                    j += 1;
+                } else if a_children.get(i + 1).map_or(false, |next| same_kind(next, b_child)) {
+                    ops.push(remove(a_child));
+                    i += 1;
+                } else {
+                    ops.push(remove(a_child));
+                    ops.push(insert(b_child));
+                    i += 1;
+                    j += 1;
+                }
+            }
+            for a_child in &a_children[i..] {
+                ops.push(remove(a_child))
+            }
+            for b_child in &b_children[j..] {
+                ops.push(insert(b_child))
+            }
+            // Reversed, so that they are popped in tree order.
+            stack.extend(matched.into_iter().rev());
+        }
+        ops
+    }
+}
+
+/// Whether two nodes can be matched with each other.
+fn same_kind(a: &NodeRef, b: &NodeRef) -> bool {
+    match (a.data(), b.data()) {
+        (&NodeData::Element(ref a), &NodeData::Element(ref b)) => a.name == b.name,
+        (&NodeData::Text(_), &NodeData::Text(_)) |
+        (&NodeData::Comment(_), &NodeData::Comment(_)) |
+        (&NodeData::Document(_), &NodeData::Document(_)) |
+        (&NodeData::DocumentFragment, &NodeData::DocumentFragment) => true,
+        (&NodeData::Doctype(ref a), &NodeData::Doctype(ref b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Compare the attributes or text of two matched nodes.
+fn compare_contents(a: &NodeRef, b: &NodeRef, ops: &mut Vec<DiffOp>) {
+    match (a.data(), b.data()) {
+        (&NodeData::Element(ref a_element), &NodeData::Element(ref b_element)) => {
+            let a_attributes = a_element.attributes.borrow();
+            let b_attributes = b_element.attributes.borrow();
+            let find = |list: &[(QualName, String)], name: &QualName| {
+                list.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value.clone())
+            };
+            for &(ref name, ref value) in &a_attributes.list {
+                let new = find(&b_attributes.list, name);
+                if new.as_ref() != Some(value) {
+                    ops.push(DiffOp::Attribute {
+                        path: path(a),
+                        name: name.clone(),
+                        old: Some(value.clone()),
+                        new: new,
+                    })
+                }
+            }
+            for &(ref name, ref value) in &b_attributes.list {
+                if find(&a_attributes.list, name).is_none() {
+                    ops.push(DiffOp::Attribute {
+                        path: path(a),
+                        name: name.clone(),
+                        old: None,
+                        new: Some(value.clone()),
+                    })
+                }
+            }
+        }
+        (&NodeData::Text(ref a_text), &NodeData::Text(ref b_text)) |
+        (&NodeData::Comment(ref a_text), &NodeData::Comment(ref b_text)) => {
+            if a_text != b_text {
+                ops.push(DiffOp::Text {
+                    path: parent_path(a),
+                    index: a.index_in_parent().unwrap_or(0),
+                    old: a_text.borrow().clone(),
+                    new: b_text.borrow().clone(),
+                })
+            }
+        }
+        _ => {}
+    }
+}
+
+fn insert(node: &NodeRef) -> DiffOp {
+    DiffOp::Insert {
+        path: parent_path(node),
+        index: node.index_in_parent().unwrap_or(0),
+        node: node.clone(),
+    }
+}
+
+fn remove(node: &NodeRef) -> DiffOp {
+    DiffOp::Remove {
+        path: parent_path(node),
+        index: node.index_in_parent().unwrap_or(0),
+        node: node.clone(),
+    }
+}
+
+fn parent_path(node: &NodeRef) -> String {
+    node.parent().map_or(String::new(), |parent| path(&parent))
+}
+
+fn path(node: &NodeRef) -> String {
+    let mut steps = node.inclusive_ancestors().elements().map(|element| {
+        css_path_step(element.as_node(), &element.name.local)
+    }).collect::<Vec<_>>();
+    steps.reverse();
+    steps.join(" > ")
+}
@@ -135,10 +135,68 @@ impl NodeRef {
         }
     }
 
+    /// Return an iterator of references to the nodes after this one in tree order,
+    /// excluding its descendants.
+    ///
+    /// This is the XPath `following` axis:
+    /// a node is included if it starts after this node ends,
+    /// which is the case for following siblings, their descendants,
+    /// and the same for each ancestor.
+    #[inline]
+    pub fn following(&self) -> Following {
+        Following(next_skipping_descendants(self))
+    }
+
+    /// Return an iterator of references to the nodes before this one,
+    /// in reverse tree order, excluding its ancestors.
+    ///
+    /// This is the XPath `preceding` axis:
+    /// a node is included if it ends before this node starts,
+    /// which is the case for preceding siblings, their descendants,
+    /// and the same for each ancestor.
+    #[inline]
+    pub fn preceding(&self) -> Preceding {
+        Preceding {
+            current: Some(self.clone()),
+            next_ancestor: self.parent(),
+        }
+    }
+
     /// Return an iterator of the inclusive descendants element that match the given selector list.
+    ///
+    /// `:scope` matches this node if it is an element, so that for example `:scope > li`
+    /// only matches `li` elements that are children of this node.
+    /// Otherwise (for example for a document node) it matches the root element.
+    ///
+    /// The tree is traversed lazily, as the iterator advances.
+    /// Mutating the tree during iteration is memory-safe but can skip or repeat nodes:
+    /// for example, detaching the last element returned ends the iteration
+    /// once its descendants are done. Use `select_collect` to mutate matched nodes.
     #[inline]
     pub fn select(&self, selectors: &str) -> Result<Select<Elements<Descendants>>, ()> {
-        self.inclusive_descendants().select(selectors)
+        let mut select = try!(self.inclusive_descendants().select(selectors));
+        select.scope = if self.as_element().is_some() { Some(self.clone()) } else { None };
+        Ok(select)
+    }
+
+    /// Return a vector of the inclusive descendants element that match the given selector list,
+    /// in tree order.
+    ///
+    /// Unlike with `select`, all matches are found before any is returned,
+    /// so the tree can be mutated freely while going through the result.
+    #[inline]
+    pub fn select_collect(&self, selectors: &str) -> Result<Vec<NodeDataRef<ElementData>>, ()> {
+        self.select(selectors).map(Iterator::collect)
+    }
+
+    /// Return the first inclusive descendant element that match the given selector list,
+    /// in tree order.
+    ///
+    /// This stops traversing the tree at the first match.
+    #[inline]
+    pub fn select_first(&self, selectors: &str)
+                        -> Result<Option<NodeDataRef<ElementData>>, ()> {
+        self.select(selectors).map(|mut iter| iter.next())
     }
 }
 
@@ -197,6 +255,72 @@ impl Iterator for Ancestors {
 }
 
 
+/// An iterator of the nodes after a given node in tree order, excluding its descendants.
+///
+/// See `NodeRef::following`.
+#[derive(Debug, Clone)]
+pub struct Following(Option<NodeRef>);
+
+impl Iterator for Following {
+    type Item = NodeRef;
+
+    #[inline]
+    fn next(&mut self) -> Option<NodeRef> {
+        self.0.take().map(|node| {
+            self.0 = node.first_child().or_else(|| next_skipping_descendants(&node));
+            node
+        })
+    }
+}
+
+/// Return the first node after `node`’s descendants in tree order.
+fn next_skipping_descendants(node: &NodeRef) -> Option<NodeRef> {
+    node.inclusive_ancestors().filter_map(|ancestor| ancestor.next_sibling()).next()
+}
+
+
+/// An iterator of the nodes before a given node in reverse tree order, excluding its ancestors.
+///
+/// See `NodeRef::preceding`.
+#[derive(Debug, Clone)]
+pub struct Preceding {
+    current: Option<NodeRef>,
+    next_ancestor: Option<NodeRef>,
+}
+
+impl Iterator for Preceding {
+    type Item = NodeRef;
+
+    fn next(&mut self) -> Option<NodeRef> {
+        loop {
+            let node = match self.current.take() {
+                Some(node) => node,
+                None => return None
+            };
+            if let Some(mut previous) = node.previous_sibling() {
+                // The last node of the previous sibling’s subtree.
+                while let Some(last_child) = previous.last_child() {
+                    previous = last_child
+                }
+                self.current = Some(previous.clone());
+                return Some(previous)
+            }
+            let parent = match node.parent() {
+                Some(parent) => parent,
+                None => return None
+            };
+            self.current = Some(parent.clone());
+            if Some(&parent) == self.next_ancestor.as_ref() {
+                // Skip ancestors of the original node.
+                self.next_ancestor = parent.parent();
+            } else {
+                return Some(parent)
+            }
+        }
+    }
+}
+
+
 /// An iterator of references to a given node and its descendants, in tree order.
 #[derive(Debug, Clone)]
 pub struct Descendants(Traverse);
@@ -346,6 +470,34 @@ where I: Iterator<Item=NodeDataRef<ElementData>>,
 
     /// The selectors to be matched.
     pub selectors: S,
+
+    /// The node that `:scope` matches, or `None` for the root element.
+    ///
+    /// `Select::new` sets this to `None`.
+    pub scope: Option<NodeRef>,
+}
+
+impl<I, S> Select<I, S>
+where I: Iterator<Item=NodeDataRef<ElementData>>,
+      S: Borrow<Selectors> {
+    /// Filter `iter` to elements matching `selectors`, with `:scope` matching the root element.
+    #[inline]
+    pub fn new(iter: I, selectors: S) -> Select<I, S> {
+        Select {
+            iter: iter,
+            selectors: selectors,
+            scope: None,
+        }
+    }
+}
+
+#[inline]
+fn select_matches(selectors: &Selectors, scope: &Option<NodeRef>,
+                  element: &NodeDataRef<ElementData>) -> bool {
+    match *scope {
+        Some(ref scope) => selectors.matches_in_scope(element, scope),
+        None => selectors.matches(element),
+    }
 }
 
 impl<I, S> Iterator for Select<I, S>
@@ -356,7 +508,7 @@ where I: Iterator<Item=NodeDataRef<ElementData>>,
     #[inline]
     fn next(&mut self) -> Option<NodeDataRef<ElementData>> {
         for element in self.iter.by_ref() {
-            if self.selectors.borrow().matches(&element) {
+            if select_matches(self.selectors.borrow(), &self.scope, &element) {
                 return Some(element)
             }
         }
@@ -370,7 +522,7 @@ where I: DoubleEndedIterator<Item=NodeDataRef<ElementData>>,
     #[inline]
     fn next_back(&mut self) -> Option<NodeDataRef<ElementData>> {
         for element in self.iter.by_ref().rev() {
-            if self.selectors.borrow().matches(&element) {
+            if select_matches(self.selectors.borrow(), &self.scope, &element) {
                 return Some(element)
             }
         }
@@ -411,10 +563,7 @@ pub trait ElementIterator: Sized + Iterator<Item=NodeDataRef<ElementData>> {
     /// Filter this element iterator to elements maching the given selectors.
     #[inline]
     fn select(self, selectors: &str) -> Result<Select<Self>, ()> {
-        Selectors::compile(selectors).map(|s| Select {
-            iter: self,
-            selectors: s,
-        })
+        Selectors::compile(selectors).map(|s| Select::new(self, s))
     }
 }
 
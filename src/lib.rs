@@ -7,28 +7,50 @@ Kuchiki (朽木), a HTML/XML tree manipulation library for Rust.
 #![cfg_attr(feature = "unstable", feature(rc_counts))]
 #![deny(missing_docs)]
 
+extern crate cssparser;
 extern crate html5ever;
 #[macro_use] extern crate matches;
 extern crate selectors;
 extern crate rc;
 #[macro_use] extern crate string_cache;
+#[cfg(all(test, feature = "serde"))] extern crate serde_json;
 #[cfg(test)] extern crate tempdir;
 
 mod attributes;
+mod charset;
+mod diff;
 #[cfg(feature = "hyper")] mod hyper;
 pub mod iter;
+mod minify;
 mod move_cell;
+mod names;
 mod node_data_ref;
 mod parser;
+mod sanitize;
 mod select;
+mod sendable;
+#[cfg(feature = "serde")] mod serde;
 mod serializer;
 #[cfg(test)] mod tests;
+mod text;
 mod tree;
+#[cfg(feature = "url")] mod url;
 
-pub use attributes::Attributes;
+pub use attributes::{Attributes, AttributesRef, AttributesIter};
+pub use diff::DiffOp;
+pub use minify::MinifyOpts;
+pub use names::{html_name, attr_name};
 pub use node_data_ref::NodeDataRef;
-pub use parser::{parse_html, ParseOpts};
+pub use parser::{parse_html, parse_html_with_options, ParseOpts};
+pub use parser::{parse_fragment, parse_fragment_with_options, parse_fragment_nodes};
+pub use parser::parse_fragment_in;
+pub use parser::{parse_html_with_source_positions, SourcePositionsParser, Sink};
+pub use parser::{parse_html_with_errors, ParseError, WithErrors};
+pub use parser::parse_html_bytes;
 pub use select::Selectors;
+pub use sendable::{SendableNode, SendableNodeData};
+pub use serializer::SerializeOpts;
+pub use text::TextExtractOpts;
 pub use tree::{NodeRef, Node, NodeData, ElementData, Doctype, DocumentData};
 
 /// This module re-exports a number of traits that are useful when using Kuchiki.
@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+use selectors::matching::SELECTOR_WHITESPACE;
+use string_cache::Atom;
+
+use iter::NodeEdge;
+use serializer::{default_inline_elements, preserves_whitespace};
+use tree::{NodeRef, NodeData};
+
+/// Options for `NodeRef::minify`.
+#[derive(Debug, Clone)]
+pub struct MinifyOpts {
+    /// Whether to remove comments. The default is `true`.
+    pub remove_comments: bool,
+
+    /// Whether to keep Internet Explorer conditional comments like `<!--[if IE]>…<![endif]-->`
+    /// when removing comments. The default is `true`.
+    pub keep_conditional_comments: bool,
+
+    /// Whether to replace each run of whitespace in text with a single space.
+    /// The default is `true`.
+    pub collapse_whitespace: bool,
+
+    /// Whether to remove whitespace at the start and end of block elements
+    /// and between block elements, rather than only collapsing it.
+    /// The default is `true`.
+    pub remove_block_whitespace: bool,
+
+    /// The local names of elements that are laid out inline with text.
+    /// Other elements are considered blocks.
+    ///
+    /// The default is the set of HTML phrasing content elements, such as `span` or `img`.
+    pub inline_elements: HashSet<Atom>,
+}
+
+impl Default for MinifyOpts {
+    fn default() -> MinifyOpts {
+        MinifyOpts {
+            remove_comments: true,
+            keep_conditional_comments: true,
+            collapse_whitespace: true,
+            remove_block_whitespace: true,
+            inline_elements: default_inline_elements(),
+        }
+    }
+}
+
+impl NodeRef {
+    /// Reduce the size of this subtree’s serialization, in place,
+    /// by removing comments and insignificant whitespace.
+    ///
+    /// The contents of elements where whitespace is significant,
+    /// like `<pre>`, `<textarea>`, `<script>`, or `<style>`, are not changed.
+    /// Whitespace next to inline elements is kept (collapsed),
+    /// since it is rendered as a space between words.
+    pub fn minify(&self, opts: MinifyOpts) {
+        if self.inclusive_ancestors().any(|node| is_preserved(&node)) {
+            return
+        }
+        if opts.remove_comments {
+            for node in self.unpreserved_descendants() {
+                if let Some(comment) = node.as_comment() {
+                    if !(opts.keep_conditional_comments && is_conditional(&comment.borrow())) {
+                        node.detach()
+                    }
+                }
+            }
+            // Text nodes on either side of a removed comment are now adjacent.
+            self.normalize();
+        }
+        for node in self.unpreserved_descendants() {
+            let text_node = match node.as_text() {
+                Some(text_node) => text_node,
+                None => continue
+            };
+            let mut text = text_node.borrow_mut();
+            if opts.collapse_whitespace {
+                *text = collapse_whitespace(&text);
+            }
+            if opts.remove_block_whitespace {
+                let at_block_start = match node.previous_sibling() {
+                    Some(previous) => is_block(&previous, &opts),
+                    None => node.parent().map_or(false, |parent| is_block(&parent, &opts)),
+                };
+                let at_block_end = match node.next_sibling() {
+                    Some(next) => is_block(&next, &opts),
+                    None => node.parent().map_or(false, |parent| is_block(&parent, &opts)),
+                };
+                if at_block_start {
+                    let trimmed = text.trim_left_matches(SELECTOR_WHITESPACE).len();
+                    let start = text.len() - trimmed;
+                    text.drain(..start);
+                }
+                if at_block_end {
+                    let end = text.trim_right_matches(SELECTOR_WHITESPACE).len();
+                    text.truncate(end);
+                }
+            }
+            if text.is_empty() {
+                node.detach()
+            }
+        }
+    }
+
+    /// Collect descendants that are not inside an element where whitespace is significant.
+    fn unpreserved_descendants(&self) -> Vec<NodeRef> {
+        let mut nodes = Vec::new();
+        let mut preserved_depth = 0;
+        for edge in self.traverse() {
+            match edge {
+                NodeEdge::Start(node) => {
+                    if is_preserved(&node) {
+                        preserved_depth += 1
+                    } else if preserved_depth == 0 {
+                        nodes.push(node)
+                    }
+                }
+                NodeEdge::End(node) => {
+                    if is_preserved(&node) {
+                        preserved_depth -= 1
+                    }
+                }
+            }
+        }
+        nodes
+    }
+}
+
+fn is_preserved(node: &NodeRef) -> bool {
+    node.as_element().map_or(false, |element| {
+        element.name.ns == ns!(html) && preserves_whitespace(&element.name.local)
+    })
+}
+
+fn is_block(node: &NodeRef, opts: &MinifyOpts) -> bool {
+    match *node.data() {
+        NodeData::Element(ref element) => !opts.inline_elements.contains(&element.name.local),
+        NodeData::Document(_) | NodeData::DocumentFragment => true,
+        _ => false,
+    }
+}
+
+fn is_conditional(comment: &str) -> bool {
+    comment.starts_with("[if") || comment.starts_with("<![endif]")
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut previous_was_whitespace = false;
+    for c in text.chars() {
+        let is_whitespace = SELECTOR_WHITESPACE.contains(&c);
+        if !is_whitespace {
+            result.push(c)
+        } else if !previous_was_whitespace {
+            result.push(' ')
+        }
+        previous_was_whitespace = is_whitespace;
+    }
+    result
+}
@@ -0,0 +1,19 @@
+use string_cache::{Atom, QualName};
+
+/// Return the name of an element in the HTML namespace, such as `html_name("div")`.
+///
+/// The local name is used as-is: HTML element names are normally lowercase.
+#[inline]
+pub fn html_name(local_name: &str) -> QualName {
+    QualName::new(ns!(html), Atom::from(local_name))
+}
+
+/// Return the name of an attribute in the null namespace, such as `attr_name("class")`.
+///
+/// This is the namespace of all attributes of HTML elements,
+/// and of most attributes of SVG and MathML elements.
+/// The local name is used as-is.
+#[inline]
+pub fn attr_name(local_name: &str) -> QualName {
+    QualName::new(ns!(), Atom::from(local_name))
+}
@@ -69,6 +69,17 @@ impl<T> NodeDataRef<T> {
     }
 }
 
+// #[derive(Clone)] would require `T: Clone`.
+impl<T> Clone for NodeDataRef<T> {
+    #[inline]
+    fn clone(&self) -> NodeDataRef<T> {
+        NodeDataRef {
+            _keep_alive: self._keep_alive.clone(),
+            _reference: self._reference,
+        }
+    }
+}
+
 impl<T> Deref for NodeDataRef<T> {
     type Target = T;
     #[inline] fn deref(&self) -> &T { unsafe { &*self._reference } }
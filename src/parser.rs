@@ -1,9 +1,16 @@
 use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::HashMap;
+use html5ever::encoding::DecoderTrap;
 use html5ever::{self, Attribute};
-use html5ever::tendril::StrTendril;
+use html5ever::tendril::{fmt, StrTendril, TendrilSink};
+use html5ever::tendril::stream::Utf8LossyDecoder;
 use html5ever::tree_builder::{TreeSink, NodeOrText, QuirksMode};
+use rc::Rc;
 use string_cache::QualName;
 
+use charset;
 use tree::NodeRef;
 
 /// Options for the HTML parser.
@@ -17,9 +24,28 @@ pub struct ParseOpts {
 
     /// A callback for HTML parse errors (which are never fatal).
     pub on_parse_error: Option<Box<FnMut(Cow<'static, str>)>>,
+
+    /// The maximum depth of nodes in the tree, counted as their number of ancestors
+    /// (so `<html>` is at depth 1 and `<body>` at depth 2).
+    ///
+    /// When a node would be deeper, it is instead appended to the ancestor of its parent
+    /// at depth `max_depth - 1`: deeper content is flattened into the children
+    /// of the elements at the limit, in document order, rather than dropped.
+    /// In template contents, depth is counted from the contents’ document fragment.
+    /// A limit of 0 is treated as 1.
+    ///
+    /// This protects code that processes the tree recursively from very deeply nested input.
+    /// The default is `None`: no limit.
+    pub max_depth: Option<usize>,
 }
 
 /// Parse an HTML document with html5ever and the default configuration.
+///
+/// The returned parser receives input through the `TendrilSink` trait’s methods.
+/// To parse from an `io::Read` stream without first reading all of it into memory,
+/// use `parse_html().from_utf8().read_from(&mut reader)`:
+/// input is read and parsed in chunks of 4 KB, UTF-8 sequences split between chunks
+/// are decoded correctly, and I/O errors are returned.
 pub fn parse_html() -> html5ever::Parser<Sink> {
     parse_html_with_options(ParseOpts::default())
 }
@@ -29,6 +55,9 @@ pub fn parse_html_with_options(opts: ParseOpts) -> html5ever::Parser<Sink> {
     let sink = Sink {
         document_node: NodeRef::new_document(),
         on_parse_error: opts.on_parse_error,
+        current_position: None,
+        errors: None,
+        max_depth: opts.max_depth,
     };
     let html5opts = html5ever::ParseOpts {
         tokenizer: opts.tokenizer,
@@ -37,9 +66,455 @@ pub fn parse_html_with_options(opts: ParseOpts) -> html5ever::Parser<Sink> {
     html5ever::parse_document(sink, html5opts)
 }
 
+/// Parse an HTML document from bytes in an unknown character encoding.
+///
+/// `declared_charset` is the encoding label given by the transport layer, if any,
+/// such as the `charset` parameter of an HTTP `Content-Type` header.
+/// The encoding is taken from, in order of priority:
+/// a byte order mark, `declared_charset` if it is a known label,
+/// a `<meta charset>` or `<meta http-equiv=Content-Type>` tag within the first 1024 bytes,
+/// and finally windows-1252, like the HTML specification’s default for unknown documents.
+///
+/// Invalid byte sequences are replaced with U+FFFD.
+pub fn parse_html_bytes(bytes: &[u8], declared_charset: Option<&str>) -> NodeRef {
+    let (encoding, bom_length) = charset::detect(bytes, declared_charset);
+    let html = encoding.decode(&bytes[bom_length..], DecoderTrap::Replace)
+        .ok().expect("decoding with DecoderTrap::Replace does not fail");
+    parse_html().one(html)
+}
+
+/// Parse an HTML document with html5ever, and collect parse errors.
+///
+/// Finishing the parser returns the document node together with the errors, in order.
+/// `opts.on_parse_error`, if any, is still called for each error.
+pub fn parse_html_with_errors(opts: ParseOpts) -> WithErrors<html5ever::Parser<Sink>> {
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let sink = Sink {
+        document_node: NodeRef::new_document(),
+        on_parse_error: opts.on_parse_error,
+        current_position: None,
+        errors: Some(errors.clone()),
+        max_depth: opts.max_depth,
+    };
+    let html5opts = html5ever::ParseOpts {
+        tokenizer: opts.tokenizer,
+        tree_builder: opts.tree_builder,
+    };
+    WithErrors {
+        parser: html5ever::parse_document(sink, html5opts),
+        errors: errors,
+    }
+}
+
+/// An HTML parse error, as reported by html5ever.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The error message.
+    pub message: Cow<'static, str>,
+
+    /// The line and column (both starting at 1) in the source
+    /// near which the error was found.
+    ///
+    /// This is only available when parsing with `parse_html_with_source_positions`.
+    pub position: Option<(usize, usize)>,
+}
+
+/// A parser that collects parse errors,
+/// ready to receive Unicode input through the `TendrilSink` trait’s methods.
+///
+/// See `parse_html_with_errors` and `SourcePositionsParser::with_errors`.
+pub struct WithErrors<P> {
+    parser: P,
+    errors: Rc<RefCell<Vec<ParseError>>>,
+}
+
+impl<P: TendrilSink<fmt::UTF8, Output=NodeRef>> WithErrors<P> {
+    /// Wrap this parser into a `TendrilSink` that accepts UTF-8 bytes.
+    ///
+    /// Decoding is lossy, like `String::from_utf8_lossy`.
+    #[inline]
+    pub fn from_utf8(self) -> Utf8LossyDecoder<Self> {
+        Utf8LossyDecoder::new(self)
+    }
+}
+
+impl<P: TendrilSink<fmt::UTF8, Output=NodeRef>> TendrilSink<fmt::UTF8> for WithErrors<P> {
+    #[inline]
+    fn process(&mut self, t: StrTendril) {
+        self.parser.process(t)
+    }
+
+    #[inline]
+    fn error(&mut self, desc: Cow<'static, str>) {
+        self.parser.error(desc)
+    }
+
+    type Output = (NodeRef, Vec<ParseError>);
+
+    fn finish(self) -> (NodeRef, Vec<ParseError>) {
+        let document = self.parser.finish();
+        let errors = ::std::mem::replace(&mut *self.errors.borrow_mut(), Vec::new());
+        (document, errors)
+    }
+}
+
+/// Parse an HTML document with html5ever,
+/// recording where each element comes from in the source.
+///
+/// Positions are available through `Node::source_position`.
+/// This costs memory for every element, and parsing is slower:
+/// the input is scanned for `<` and `>` characters,
+/// and fed to html5ever in separate chunks that each end at a `>`,
+/// which adds overhead for every tag.
+pub fn parse_html_with_source_positions(opts: ParseOpts) -> SourcePositionsParser {
+    let current_position = Rc::new(Cell::new(SourceCursor { tag_start: (1, 1), end: (1, 1) }));
+    let document_node = NodeRef::new_document();
+    *document_node.as_document().unwrap()._source_positions.borrow_mut() = Some(HashMap::new());
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let sink = Sink {
+        document_node: document_node,
+        on_parse_error: opts.on_parse_error,
+        current_position: Some(current_position.clone()),
+        errors: Some(errors.clone()),
+        max_depth: opts.max_depth,
+    };
+    let html5opts = html5ever::ParseOpts {
+        tokenizer: opts.tokenizer,
+        tree_builder: opts.tree_builder,
+    };
+    SourcePositionsParser {
+        parser: html5ever::parse_document(sink, html5opts),
+        current_position: current_position,
+        errors: errors,
+        line: 1,
+        column: 1,
+        pending_less_than: None,
+    }
+}
+
+/// An HTML parser that records source positions,
+/// ready to receive Unicode input through the `TendrilSink` trait’s methods.
+///
+/// See `parse_html_with_source_positions`.
+pub struct SourcePositionsParser {
+    parser: html5ever::Parser<Sink>,
+    current_position: Rc<Cell<SourceCursor>>,
+    errors: Rc<RefCell<Vec<ParseError>>>,
+    line: usize,
+    column: usize,
+    /// The position of a `<` that ended the previous input, if any.
+    pending_less_than: Option<(usize, usize)>,
+}
+
+/// Where a `SourcePositionsParser` is in the source, shared with its `Sink`.
+#[derive(Debug, Clone, Copy)]
+struct SourceCursor {
+    /// The position of the `<` of the last start tag fed to html5ever.
+    tag_start: (usize, usize),
+    /// The position of the last character fed to html5ever.
+    end: (usize, usize),
+}
+
+impl SourcePositionsParser {
+    /// Wrap this parser into a `TendrilSink` that accepts UTF-8 bytes.
+    ///
+    /// Decoding is lossy, like `String::from_utf8_lossy`.
+    #[inline]
+    pub fn from_utf8(self) -> Utf8LossyDecoder<Self> {
+        Utf8LossyDecoder::new(self)
+    }
+
+    /// Also collect parse errors, with their source position.
+    ///
+    /// Finishing the parser then returns the document node together with the errors.
+    #[inline]
+    pub fn with_errors(self) -> WithErrors<Self> {
+        let errors = self.errors.clone();
+        WithErrors {
+            parser: self,
+            errors: errors,
+        }
+    }
+}
+
+impl SourcePositionsParser {
+    #[inline]
+    fn set_end(&self) {
+        let mut cursor = self.current_position.get();
+        cursor.end = (self.line, self.column);
+        self.current_position.set(cursor);
+    }
+}
+
+impl TendrilSink<fmt::UTF8> for SourcePositionsParser {
+    fn process(&mut self, t: StrTendril) {
+        // Feed the input up to and including each `>` separately,
+        // so that a start tag is processed right after the position of its `<` is known.
+        // A start tag is a `<` followed by an ASCII letter.
+        let mut start = 0;
+        for (i, c) in t.char_indices() {
+            if let Some(position) = self.pending_less_than.take() {
+                if matches!(c, 'a'...'z' | 'A'...'Z') {
+                    let mut cursor = self.current_position.get();
+                    cursor.tag_start = position;
+                    self.current_position.set(cursor);
+                }
+            }
+            if c == '<' {
+                self.pending_less_than = Some((self.line, self.column));
+            }
+            if c == '>' {
+                self.set_end();
+                let end = i + 1;
+                self.parser.process(t.subtendril(start as u32, (end - start) as u32));
+                start = end;
+            }
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        if start < t.len() {
+            self.set_end();
+            self.parser.process(t.subtendril(start as u32, (t.len() - start) as u32));
+        }
+    }
+
+    fn error(&mut self, desc: Cow<'static, str>) {
+        self.parser.error(desc)
+    }
+
+    type Output = NodeRef;
+
+    fn finish(self) -> NodeRef {
+        self.parser.finish()
+    }
+}
+
+/// Parse an HTML fragment with html5ever and the default configuration.
+///
+/// The fragment is parsed as if it were the contents of an element
+/// with the given name and attributes,
+/// so that for example `<tr>` is only kept in a `<table>` or `<tbody>` context.
+///
+/// The output is a document node whose only child is a synthetic `<html>` element.
+/// The parsed nodes are the children of that element.
+///
+/// The fragment is parsed in no-quirks mode. See `parse_fragment_in`.
+pub fn parse_fragment(ctx_name: QualName, ctx_attrs: Vec<(QualName, String)>)
+                      -> html5ever::Parser<Sink> {
+    parse_fragment_with_options(ParseOpts::default(), ctx_name, ctx_attrs)
+}
+
+/// Parse an HTML fragment with html5ever, in the context of the given element
+/// of a document in the given quirks mode.
+///
+/// This should be the quirks mode of the document that parsed nodes are inserted into,
+/// since it affects parsing: for example, in quirks mode a `<table>` start tag
+/// does not close an open `<p>` element.
+/// See `parse_fragment`.
+pub fn parse_fragment_in(ctx_name: QualName, ctx_attrs: Vec<(QualName, String)>,
+                         quirks_mode: QuirksMode) -> html5ever::Parser<Sink> {
+    let mut opts = ParseOpts::default();
+    opts.tree_builder.quirks_mode = quirks_mode;
+    parse_fragment_with_options(opts, ctx_name, ctx_attrs)
+}
+
+/// Parse an HTML fragment with html5ever, in the context of the given element.
+///
+/// See `parse_fragment`.
+pub fn parse_fragment_with_options(opts: ParseOpts, ctx_name: QualName,
+                                   ctx_attrs: Vec<(QualName, String)>)
+                                   -> html5ever::Parser<Sink> {
+    let sink = Sink {
+        document_node: NodeRef::new_document(),
+        on_parse_error: opts.on_parse_error,
+        current_position: None,
+        errors: None,
+        max_depth: opts.max_depth,
+    };
+    let html5opts = html5ever::ParseOpts {
+        tokenizer: opts.tokenizer,
+        tree_builder: opts.tree_builder,
+    };
+    let ctx_attrs = ctx_attrs.into_iter().map(|(name, value)| Attribute {
+        name: name,
+        value: value.into(),
+    }).collect();
+    html5ever::parse_fragment(sink, html5opts, ctx_name, ctx_attrs)
+}
+
+/// Parse an HTML fragment in the context of the given element,
+/// and return the parsed top-level nodes, detached and in order.
+///
+/// Unlike `parse_fragment`, there is no synthetic document or `<html>` element:
+/// the returned nodes have no parent and are ready to be inserted elsewhere.
+///
+/// The fragment is parsed in no-quirks mode.
+pub fn parse_fragment_nodes(ctx_name: QualName, ctx_attrs: Vec<(QualName, String)>, html: &str)
+                            -> Vec<NodeRef> {
+    fragment_nodes(parse_fragment(ctx_name, ctx_attrs).one(html))
+}
+
+fn fragment_nodes(document: NodeRef) -> Vec<NodeRef> {
+    // The fragment parser puts parsed nodes into a synthetic `<html>` root element.
+    let root = document.first_child().unwrap();
+    let nodes = root.children().collect::<Vec<_>>();
+    for node in &nodes {
+        node.detach()
+    }
+    nodes
+}
+
+impl NodeRef {
+    /// Replace this node’s children with the result of parsing an HTML fragment.
+    ///
+    /// If this node is an element, it is used as the context for parsing
+    /// (so that for example `<tr>` can be parsed into a `<table>`).
+    /// Otherwise, the fragment is parsed as if inside a `<body>` element.
+    /// The fragment is parsed in the quirks mode of this node’s document, if any.
+    /// The attributes of this node are not affected.
+    pub fn set_inner_html(&self, html: &str) {
+        let nodes = self.parse_fragment_in_context(html);
+        self.remove_children();
+        self.append_children(nodes)
+    }
+
+    /// Parse an HTML fragment and append the resulting nodes, in order,
+    /// after this node’s existing children.
+    ///
+    /// The fragment is parsed in the context of this node, like with `set_inner_html`.
+    /// Parsing never fails: like in browsers, invalid markup is recovered from.
+    pub fn append_html(&self, html: &str) {
+        let nodes = self.parse_fragment_in_context(html);
+        self.append_children(nodes)
+    }
+
+    /// Parse an HTML fragment and insert the resulting nodes, in order,
+    /// before this node’s existing children.
+    ///
+    /// The fragment is parsed in the context of this node, like with `set_inner_html`.
+    /// Parsing never fails: like in browsers, invalid markup is recovered from.
+    pub fn prepend_html(&self, html: &str) {
+        let nodes = self.parse_fragment_in_context(html);
+        match self.first_child() {
+            Some(first_child) => first_child.insert_children_before(nodes),
+            None => self.append_children(nodes)
+        }
+    }
+
+    /// Parse an HTML fragment that contains a single element, and return that element.
+    ///
+    /// The fragment is parsed as if inside a `<body>` element.
+    /// Whitespace around the element is ignored.
+    ///
+    /// Panics if the fragment contains anything else. See `try_from_html`.
+    pub fn from_html(html: &str) -> NodeRef {
+        match NodeRef::try_from_html(html) {
+            Ok(element) => element,
+            Err(()) => panic!("expected a single element, got {:?}", html)
+        }
+    }
+
+    /// Parse an HTML fragment that contains a single element, and return that element.
+    ///
+    /// The fragment is parsed as if inside a `<body>` element.
+    /// Whitespace around the element is ignored.
+    /// Return an error if there is not exactly one top-level element,
+    /// or if there is a top-level comment or non-whitespace text.
+    pub fn try_from_html(html: &str) -> Result<NodeRef, ()> {
+        let mut element = None;
+        for node in parse_fragment_nodes(qualname!(html, "body"), Vec::new(), html) {
+            if node.as_element().is_some() && element.is_none() {
+                element = Some(node)
+            } else if !node.is_whitespace_only() || node.as_text().is_none() {
+                return Err(())
+            }
+        }
+        element.ok_or(())
+    }
+
+    fn parse_fragment_in_context(&self, html: &str) -> Vec<NodeRef> {
+        let ctx_name = match self.as_element() {
+            Some(element) => element.name.clone(),
+            None => qualname!(html, "body"),
+        };
+        let quirks_mode = match self.document() {
+            Some(document) => document.quirks_mode(),
+            None => QuirksMode::NoQuirks,
+        };
+        fragment_nodes(parse_fragment_in(ctx_name, Vec::new(), quirks_mode).one(html))
+    }
+}
+
+/// The html5ever `TreeSink` that builds Kuchiki trees.
+///
+/// The parsing functions of this crate use it internally.
+/// It can also be given to html5ever’s own parsing functions,
+/// for example to use tokenizer or tree builder options in a different way.
+/// Finishing the parser returns the document node.
+///
+/// ```rust
+/// extern crate html5ever;
+/// extern crate kuchiki;
+///
+/// use html5ever::tendril::TendrilSink;
+///
+/// fn main() {
+///     let mut opts = html5ever::ParseOpts::default();
+///     opts.tree_builder.scripting_enabled = false;
+///     let document = html5ever::parse_document(kuchiki::Sink::default(), opts)
+///         .one("<noscript><p>Foo</p></noscript>");
+///     assert_eq!(document.to_string(),
+///                "<html><head><noscript></noscript></head><body><p>Foo</p></body></html>");
+/// }
+/// ```
 pub struct Sink {
     document_node: NodeRef,
     on_parse_error: Option<Box<FnMut(Cow<'static, str>)>>,
+    current_position: Option<Rc<Cell<SourceCursor>>>,
+    errors: Option<Rc<RefCell<Vec<ParseError>>>>,
+    max_depth: Option<usize>,
+}
+
+impl Default for Sink {
+    #[inline]
+    fn default() -> Sink {
+        Sink {
+            document_node: NodeRef::new_document(),
+            on_parse_error: None,
+            current_position: None,
+            errors: None,
+            max_depth: None,
+        }
+    }
+}
+
+impl Sink {
+    /// Create a sink that calls the given function for each parse error.
+    #[inline]
+    pub fn with_parse_error_handler(on_parse_error: Box<FnMut(Cow<'static, str>)>) -> Sink {
+        Sink {
+            on_parse_error: Some(on_parse_error),
+            ..Sink::default()
+        }
+    }
+
+    /// Return the node that children of `parent` are appended to:
+    /// `parent` itself, or its ancestor at the maximum depth for parents.
+    fn append_target(&self, parent: NodeRef) -> NodeRef {
+        let max_parent_depth = match self.max_depth {
+            Some(max_depth) => cmp::max(max_depth, 1) - 1,
+            None => return parent
+        };
+        let mut target = parent;
+        for _ in max_parent_depth..target.depth() {
+            target = target.parent().unwrap()
+        }
+        target
+    }
 }
 
 impl TreeSink for Sink {
@@ -51,6 +526,12 @@ impl TreeSink for Sink {
 
     #[inline]
     fn parse_error(&mut self, message: Cow<'static, str>) {
+        if let Some(ref errors) = self.errors {
+            errors.borrow_mut().push(ParseError {
+                message: message.clone(),
+                position: self.current_position.as_ref().map(|position| position.get().end),
+            })
+        }
         if let Some(ref mut handler) = self.on_parse_error {
             handler(message)
         }
@@ -63,7 +544,7 @@ impl TreeSink for Sink {
 
     #[inline]
     fn set_quirks_mode(&mut self, mode: QuirksMode) {
-        self.document_node.as_document().unwrap()._quirks_mode.set(mode)
+        self.document_node.as_document().unwrap().set_quirks_mode(mode)
     }
 
     #[inline]
@@ -79,7 +560,14 @@ impl TreeSink for Sink {
     #[inline]
     fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>) -> NodeRef {
         let attrs = attrs.into_iter().map(|Attribute { name, value }| (name, value.into()));
-        NodeRef::new_element(name, attrs)
+        let element = NodeRef::new_element(name, attrs);
+        if let Some(ref position) = self.current_position {
+            let document = self.document_node.as_document().unwrap();
+            let key = &*element.0 as *const _ as usize;
+            document._source_positions.borrow_mut().as_mut().unwrap()
+                .insert(key, (Rc::downgrade(&element.0), position.get().tag_start));
+        }
+        element
     }
 
     #[inline]
@@ -89,6 +577,7 @@ impl TreeSink for Sink {
 
     #[inline]
     fn append(&mut self, parent: NodeRef, child: NodeOrText<NodeRef>) {
+        let parent = self.append_target(parent);
         match child {
             NodeOrText::AppendNode(node) => parent.append(node),
             NodeOrText::AppendText(text) => {
@@ -135,7 +624,9 @@ impl TreeSink for Sink {
         let element = target.as_element().unwrap();
         let mut attributes = element.attributes.borrow_mut();
         for Attribute { name, value } in attrs {
-            attributes.map.entry(name).or_insert_with(|| value.into());
+            if !attributes.list.iter().any(|&(ref existing, _)| *existing == name) {
+                attributes.list.push((name, value.into()))
+            }
         }
     }
 
@@ -146,6 +637,10 @@ impl TreeSink for Sink {
 
     #[inline]
     fn reparent_children(&mut self, node: NodeRef, new_parent: NodeRef) {
+        let new_parent = self.append_target(new_parent);
+        if new_parent == node {
+            return
+        }
         // FIXME: Can this be done more effciently in rctree,
         // by moving the whole linked list of children at once?
         for child in node.children() {
@@ -0,0 +1,86 @@
+use std::ascii::AsciiExt;
+use std::collections::{HashMap, HashSet};
+
+use tree::NodeRef;
+
+impl NodeRef {
+    /// Remove elements, attributes, and comments that are not in an allowlist
+    /// from the descendants of this node.
+    ///
+    /// Elements are matched by local name.
+    /// Attributes are kept only if they are in the null namespace and their local name is
+    /// in the set for their element’s local name in `allowed_attributes`,
+    /// or in the set for `"*"` which applies to every element.
+    /// Attributes with a `javascript:` or `vbscript:` URL, like `href` or `src`,
+    /// are always removed.
+    ///
+    /// Disallowed elements are replaced by their children if `unwrap_disallowed` is true,
+    /// or removed with their descendants otherwise.
+    /// The contents of `<script>`, `<style>`, and `<template>` are never kept
+    /// unless the element is allowed.
+    pub fn sanitize(&self, allowed_elements: &HashSet<&str>,
+                    allowed_attributes: &HashMap<&str, HashSet<&str>>,
+                    unwrap_disallowed: bool) {
+        // Collect first, since changing the tree during a traversal would disturb it.
+        // Children of an unwrapped element come after it, so they are still visited.
+        let nodes = self.descendants().collect::<Vec<_>>();
+        for node in nodes {
+            if node.as_comment().is_some() {
+                node.detach();
+                continue
+            }
+            let element = match node.as_element() {
+                Some(element) => element,
+                None => continue
+            };
+            let local_name = &*element.name.local;
+            if !allowed_elements.contains(local_name) {
+                let drop_contents = match local_name {
+                    "script" | "style" | "template" => true,
+                    _ => false,
+                };
+                if unwrap_disallowed && !drop_contents {
+                    node.unwrap()
+                } else {
+                    node.detach()
+                }
+                continue
+            }
+            let for_element = allowed_attributes.get(local_name);
+            let for_all = allowed_attributes.get("*");
+            element.attributes.borrow_mut().list.retain(|&(ref name, ref value)| {
+                let local_name = &*name.local;
+                name.ns == ns!() &&
+                (for_element.map_or(false, |names| names.contains(local_name)) ||
+                 for_all.map_or(false, |names| names.contains(local_name))) &&
+                !(is_url_attribute(local_name) && is_script_url(value))
+            });
+        }
+    }
+}
+
+fn is_url_attribute(local_name: &str) -> bool {
+    match local_name {
+        "href" | "src" | "action" | "formaction" | "background" | "cite" | "poster"
+        | "longdesc" | "lowsrc" | "dynsrc" | "data" | "codebase" => true,
+        _ => false,
+    }
+}
+
+/// Return whether a URL uses the `javascript:` or `vbscript:` scheme.
+///
+/// Like browsers, ignore leading whitespace and control characters,
+/// and tabs and newlines anywhere.
+fn is_script_url(url: &str) -> bool {
+    let url = url.trim_left_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|&c| c != '\t' && c != '\n' && c != '\r')
+        .collect::<String>();
+    match url.find(':') {
+        Some(i) => {
+            let scheme = &url[..i];
+            scheme.eq_ignore_ascii_case("javascript") || scheme.eq_ignore_ascii_case("vbscript")
+        }
+        None => false
+    }
+}
@@ -1,11 +1,17 @@
-use iter::{NodeIterator, Select};
+use cssparser::{self, Token};
+use iter::{NodeIterator, Select, Elements, Descendants};
 use node_data_ref::NodeDataRef;
 use selectors::{self, parser, matching};
 use selectors::parser::{AttrSelector, NamespaceConstraint, Selector, SelectorImpl, ParserContext};
 use std::ascii::AsciiExt;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
 use string_cache::{Atom, Namespace};
 use tree::{NodeRef, NodeData, ElementData};
 
+#[derive(Clone)]
 pub struct KuchikiSelectors;
 
 impl SelectorImpl for KuchikiSelectors {
@@ -24,9 +30,20 @@ impl SelectorImpl for KuchikiSelectors {
         else if name.eq_ignore_ascii_case("disabled") { Ok(Disabled) }
         else if name.eq_ignore_ascii_case("checked") { Ok(Checked) }
         else if name.eq_ignore_ascii_case("indeterminate") { Ok(Indeterminate) }
+        else if name.eq_ignore_ascii_case("scope") { Ok(Scope) }
         else { Err(()) }
     }
 
+    fn parse_non_ts_functional_pseudo_class(context: &ParserContext, name: &str,
+                                            arguments: &mut cssparser::Parser)
+                                            -> Result<PseudoClass, ()> {
+        if name.eq_ignore_ascii_case(CASE_INSENSITIVE_ATTRIBUTE) {
+            parse_case_insensitive_attribute(context, arguments)
+        } else {
+            Err(())
+        }
+    }
+
     fn parse_pseudo_element(_context: &ParserContext, _name: &str) -> Result<PseudoElement, ()> {
         Err(())
     }
@@ -44,6 +61,40 @@ pub enum PseudoClass {
     Disabled,
     Checked,
     Indeterminate,
+    Scope,
+    /// An attribute selector with the `i` flag and an operator other than `=`,
+    /// which the selectors crate does not support. The value is in ASCII lowercase.
+    AttrCaseInsensitive(AttrSelector, AttrOperator, String),
+}
+
+/// The operator of `PseudoClass::AttrCaseInsensitive`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AttrOperator {
+    /// `~=`
+    Includes,
+    /// `|=`
+    DashMatch,
+    /// `^=`
+    Prefix,
+    /// `*=`
+    Substring,
+    /// `$=`
+    Suffix,
+}
+
+thread_local! {
+    /// The element that `:scope` matches during `Selectors::matches_in_scope`.
+    static SCOPE: RefCell<Option<NodeRef>> = RefCell::new(None)
+}
+
+/// Restores the previous `SCOPE` when dropped, even if matching panics.
+struct RestoreScope(Option<NodeRef>);
+
+impl Drop for RestoreScope {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        SCOPE.with(|s| *s.borrow_mut() = previous)
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, Hash)]
@@ -58,11 +109,11 @@ impl selectors::Element for NodeDataRef<ElementData> {
     }
     #[inline]
     fn first_child_element(&self) -> Option<Self> {
-        self.as_node().children().elements().next()
+        self.as_node().first_element_child()
     }
     #[inline]
     fn last_child_element(&self) -> Option<Self> {
-        self.as_node().children().rev().elements().next()
+        self.as_node().last_element_child()
     }
     #[inline]
     fn prev_sibling_element(&self) -> Option<Self> {
@@ -126,7 +177,7 @@ impl selectors::Element for NodeDataRef<ElementData> {
         } else {
             &attr.name
         };
-        self.attributes.borrow().map.iter().any(|(key, value)| {
+        self.attributes.borrow().list.iter().any(|&(ref key, ref value)| {
             !matches!(attr.namespace, NamespaceConstraint::Specific(ref ns) if *ns != key.ns) &&
             key.local == *name &&
             test(value)
@@ -142,35 +193,297 @@ impl selectors::Element for NodeDataRef<ElementData> {
                 matches!(self.name.local, atom!("a") | atom!("area") | atom!("link")) &&
                 self.attributes.borrow().contains(atom!("href"))
             }
+            Scope => SCOPE.with(|scope| match *scope.borrow() {
+                Some(ref scope) => scope == self.as_node(),
+                None => self.is_root(),
+            }),
+            AttrCaseInsensitive(attr, operator, value) => self.match_attr(&attr, |attr_value| {
+                let attr_value = attr_value.to_ascii_lowercase();
+                match operator {
+                    AttrOperator::Includes => {
+                        attr_value.split(selectors::matching::SELECTOR_WHITESPACE)
+                        .any(|v| v == value)
+                    }
+                    AttrOperator::DashMatch => {
+                        attr_value == value ||
+                        (attr_value.starts_with(&*value) && attr_value[value.len()..].starts_with('-'))
+                    }
+                    AttrOperator::Prefix => attr_value.starts_with(&*value),
+                    AttrOperator::Substring => attr_value.contains(&*value),
+                    AttrOperator::Suffix => attr_value.ends_with(&*value),
+                }
+            }),
         }
     }
 }
 
+/// The name of the functional pseudo-class that `Selectors::compile` rewrites
+/// attribute selectors like `[href^=HTTPS i]` to, as `:-kuchiki-attr-i(href^=HTTPS)`.
+const CASE_INSENSITIVE_ATTRIBUTE: &'static str = "-kuchiki-attr-i";
+
+/// Rewrite attribute selectors with the `i` flag and an operator other than `=`
+/// to the `CASE_INSENSITIVE_ATTRIBUTE` pseudo-class, anywhere in a selector list.
+/// Everything else is copied unchanged.
+///
+/// Fails if such an attribute selector is not closed with `]`.
+fn rewrite_case_insensitive_attributes(input: &mut cssparser::Parser, output: &mut String)
+                                       -> Result<(), ()> {
+    loop {
+        let start = input.position();
+        let closing = match input.next_including_whitespace_and_comments() {
+            Err(()) => return Ok(()),
+            Ok(Token::SquareBracketBlock) => {
+                match input.parse_nested_block(case_insensitive_attribute_contents) {
+                    Ok(contents) => {
+                        if !input.slice_from(start).ends_with(']') {
+                            return Err(())
+                        }
+                        output.push(':');
+                        output.push_str(CASE_INSENSITIVE_ATTRIBUTE);
+                        output.push('(');
+                        output.push_str(contents);
+                        output.push(')');
+                    }
+                    Err(()) => output.push_str(input.slice_from(start)),
+                }
+                continue
+            }
+            Ok(Token::Function(_)) | Ok(Token::ParenthesisBlock) => ')',
+            Ok(Token::CurlyBracketBlock) => '}',
+            Ok(_) => {
+                output.push_str(input.slice_from(start));
+                continue
+            }
+        };
+        output.push_str(input.slice_from(start));
+        try!(input.parse_nested_block(|input| rewrite_case_insensitive_attributes(input, output)));
+        // Like the original input, leave blocks that end with the input unclosed.
+        if input.slice_from(start).ends_with(closing) {
+            output.push(closing);
+        }
+    }
+}
+
+/// If the contents of an attribute selector end with an operator other than `=`,
+/// a value, and the `i` flag, return them without the flag.
+fn case_insensitive_attribute_contents<'i, 't>(input: &mut cssparser::Parser<'i, 't>)
+                                               -> Result<&'i str, ()> {
+    let start = input.position();
+    loop {
+        match try!(input.next()) {
+            Token::IncludeMatch | Token::DashMatch | Token::PrefixMatch |
+            Token::SubstringMatch | Token::SuffixMatch => break,
+            _ => {}
+        }
+    }
+    try!(input.expect_ident_or_string());
+    let end = input.position();
+    match try!(input.next()) {
+        Token::Ident(ref flag) if flag.eq_ignore_ascii_case("i") => {}
+        _ => return Err(())
+    }
+    Ok(input.slice(start..end))
+}
+
+/// Parse the arguments of the `CASE_INSENSITIVE_ATTRIBUTE` pseudo-class,
+/// like the contents of an attribute selector without the flag.
+fn parse_case_insensitive_attribute(context: &ParserContext, input: &mut cssparser::Parser)
+                                    -> Result<PseudoClass, ()> {
+    let local_name_after_bar = |input: &mut cssparser::Parser| {
+        try!(input.expect_delim('|'));
+        input.expect_ident().map(Cow::into_owned)
+    };
+    let (namespace, name) = match try!(input.next()) {
+        Token::Ident(name) => match input.try(&local_name_after_bar) {
+            Ok(local_name) => {
+                match context.namespace_prefixes.get(&*name) {
+                    Some(namespace) => (NamespaceConstraint::Specific(namespace.clone()), local_name),
+                    None => return Err(()),
+                }
+            }
+            Err(()) => (NamespaceConstraint::Specific(ns!()), name.into_owned()),
+        },
+        Token::Delim('*') => (NamespaceConstraint::Any, try!(local_name_after_bar(input))),
+        Token::Delim('|') => {
+            (NamespaceConstraint::Specific(ns!()), try!(input.expect_ident()).into_owned())
+        }
+        _ => return Err(()),
+    };
+    let operator = match try!(input.next()) {
+        Token::IncludeMatch => AttrOperator::Includes,
+        Token::DashMatch => AttrOperator::DashMatch,
+        Token::PrefixMatch => AttrOperator::Prefix,
+        Token::SubstringMatch => AttrOperator::Substring,
+        Token::SuffixMatch => AttrOperator::Suffix,
+        _ => return Err(()),
+    };
+    let value = try!(input.expect_ident_or_string()).to_ascii_lowercase();
+    let attr = AttrSelector {
+        lower_name: Atom::from(&*name.to_ascii_lowercase()),
+        name: Atom::from(&*name),
+        namespace: namespace,
+    };
+    Ok(PseudoClass::AttrCaseInsensitive(attr, operator, value))
+}
+
 /// A pre-compiled list of CSS Selectors.
+///
+/// Compiling once and reusing the result with `NodeRef::select_with` or `filter`
+/// avoids parsing the selectors again for each document.
+/// This can be cloned and sent to other threads.
+#[derive(Clone)]
 pub struct Selectors(Vec<Selector<KuchikiSelectors>>);
 
 impl Selectors {
     /// Compile a list of selectors. This may fail on syntax errors or unsupported selectors.
+    ///
+    /// Type selectors without a namespace prefix match elements in any namespace.
+    /// The `html`, `svg`, `math`, `xlink`, and `xml` namespace prefixes are predefined,
+    /// so that for example `svg|a` only matches SVG `<a>` elements.
+    ///
+    /// The `i` flag for ASCII case-insensitive attribute values, as in `[href^=HTTPS i]`,
+    /// is supported with all attribute selector operators.
     #[inline]
     pub fn compile(s: &str) -> Result<Selectors, ()> {
-        parser::parse_author_origin_selector_list_from_str(s).map(Selectors)
+        let mut context = ParserContext::new();
+        context.namespace_prefixes.insert("html".to_owned(), ns!(html));
+        context.namespace_prefixes.insert("svg".to_owned(), ns!(svg));
+        context.namespace_prefixes.insert("math".to_owned(), ns!(mathml));
+        context.namespace_prefixes.insert("xlink".to_owned(), ns!(xlink));
+        context.namespace_prefixes.insert("xml".to_owned(), ns!(xml));
+        let mut rewritten = String::with_capacity(s.len());
+        try!(rewrite_case_insensitive_attributes(&mut cssparser::Parser::new(s), &mut rewritten));
+        parser::parse_selector_list(&context, &mut cssparser::Parser::new(&rewritten))
+            .map(Selectors)
     }
 
     /// Returns whether the given element matches this list of selectors.
+    ///
+    /// This matches a single element without traversing the tree for candidates,
+    /// but combinators and pseudo-classes like `:first-child` are still evaluated
+    /// against the element’s current ancestors and siblings in the tree.
+    /// Use `NodeRef::into_element_ref` to get an element from a `NodeRef`.
+    ///
+    /// `:scope` matches the root element.
     #[inline]
     pub fn matches(&self, element: &NodeDataRef<ElementData>) -> bool {
         matching::matches(&self.0, element, None)
     }
 
+    /// Returns whether the given element matches this list of selectors,
+    /// with `:scope` matching the `scope` node.
+    ///
+    /// For example, `:scope > li` only matches `li` elements that are children of `scope`.
+    pub fn matches_in_scope(&self, element: &NodeDataRef<ElementData>, scope: &NodeRef) -> bool {
+        let previous = SCOPE.with(|s| mem::replace(&mut *s.borrow_mut(), Some(scope.clone())));
+        let _guard = RestoreScope(previous);
+        self.matches(element)
+    }
+
     /// Filter an element iterator, yielding those matching this list of selectors.
     #[inline]
     pub fn filter<I>(&self, iter: I) -> Select<I, &Selectors>
     where I: Iterator<Item=NodeDataRef<ElementData>> {
+        Select::new(iter, self)
+    }
+}
+
+impl NodeRef {
+    /// Return an iterator of the inclusive descendant elements that match
+    /// the given pre-compiled selector list.
+    ///
+    /// This is like `select`, without parsing selectors again.
+    #[inline]
+    pub fn select_with<'a>(&self, selectors: &'a Selectors)
+                           -> Select<Elements<Descendants>, &'a Selectors> {
         Select {
-            iter: iter,
-            selectors: self,
+            iter: self.inclusive_descendants().elements(),
+            selectors: selectors,
+            scope: if self.as_element().is_some() { Some(self.clone()) } else { None },
         }
     }
+
+    /// Return whether this node is an element that matches the given selector list.
+    ///
+    /// Combinators are evaluated against this node’s actual ancestors and siblings.
+    #[inline]
+    pub fn matches(&self, selectors: &str) -> Result<bool, ()> {
+        let selectors = try!(Selectors::compile(selectors));
+        Ok(match self.clone().into_element_ref() {
+            Some(element) => selectors.matches(&element),
+            None => false,
+        })
+    }
+
+    /// Return the nearest inclusive ancestor element that matches the given selector list.
+    ///
+    /// Like `Element.closest` in the DOM, this node itself is considered first.
+    /// For non-element nodes, matching starts at their parent element.
+    #[inline]
+    pub fn closest(&self, selectors: &str) -> Result<Option<NodeDataRef<ElementData>>, ()> {
+        let selectors = try!(Selectors::compile(selectors));
+        Ok(self.inclusive_ancestors().elements().find(|element| selectors.matches(element)))
+    }
+
+    /// Return a selector that selects exactly this element among the descendants of the root
+    /// of its tree, such as `html > body > div:nth-child(2) > p`.
+    ///
+    /// Each step is the element’s local name, with `:nth-child()` if it has element siblings.
+    /// The path stops at an element with an `id` that is unique in the tree,
+    /// which is written as `#id` instead.
+    /// The tree is traversed at most once to find which ids are unique.
+    /// For non-element nodes, this is the path to their parent element.
+    /// Nodes without an element inclusive ancestor, like documents, get an empty string.
+    pub fn css_path(&self) -> String {
+        // How many elements of the tree have each id, counted at the first ancestor with one.
+        let mut id_counts = None;
+        let mut steps = Vec::new();
+        for element in self.inclusive_ancestors().elements() {
+            if let Some(id) = element.attributes.borrow().get(atom!("id")) {
+                if id_counts.is_none() {
+                    let mut counts = HashMap::new();
+                    for other in self.root().descendants().elements() {
+                        if let Some(other_id) = other.attributes.borrow().get(atom!("id")) {
+                            *counts.entry(other_id.to_owned()).or_insert(0) += 1
+                        }
+                    }
+                    id_counts = Some(counts)
+                }
+                let unique = id_counts.as_ref().unwrap().get(id) == Some(&1);
+                if unique && is_css_identifier(id) {
+                    steps.push(format!("#{}", id));
+                    break
+                }
+            }
+            steps.push(css_path_step(element.as_node(), &element.name.local));
+        }
+        steps.reverse();
+        steps.join(" > ")
+    }
+}
+
+/// Return a step of `css_path` that does not use `id`:
+/// the local name, with `:nth-child()` if the element has element siblings.
+pub fn css_path_step(node: &NodeRef, local_name: &Atom) -> String {
+    let index = node.element_index_in_parent().map_or(1, |index| index + 1);
+    if index > 1 || node.following_siblings().elements().next().is_some() {
+        format!("{}:nth-child({})", &**local_name, index)
+    } else {
+        (&**local_name).to_owned()
+    }
+}
+
+/// Return whether `s` can be used as-is as an identifier in a selector.
+fn is_css_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c >= '\u{80}' || (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') => {}
+        _ => return false
+    }
+    chars.all(|c| {
+        c == '_' || c == '-' || c >= '\u{80}' ||
+        (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9')
+    })
 }
 
 impl ::std::str::FromStr for Selectors {
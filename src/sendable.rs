@@ -0,0 +1,146 @@
+use html5ever::tree_builder::QuirksMode;
+use std::cell::RefCell;
+use string_cache::QualName;
+
+use attributes::Attributes;
+use iter::NodeEdge;
+use tree::{NodeRef, NodeData, ElementData, Doctype};
+
+/// An owned copy of a subtree that does not use `Rc` or `RefCell`,
+/// so that it can be sent to another thread.
+///
+/// Create one with `NodeRef::into_sendable`, and turn it back into a tree with `into_node`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SendableNode {
+    /// The node-type-specific data.
+    pub data: SendableNodeData,
+
+    /// The children of this node, in tree order.
+    pub children: Vec<SendableNode>,
+}
+
+/// Node data specific to the node type, in a `SendableNode`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SendableNodeData {
+    /// Element node
+    Element {
+        /// The namespace and local name of the element.
+        name: QualName,
+
+        /// The attributes of the element, in order.
+        attributes: Vec<(QualName, String)>,
+
+        /// If the element is an HTML `<template>` element,
+        /// the document fragment node that is the root of template contents.
+        template_contents: Option<Box<SendableNode>>,
+    },
+
+    /// Text node
+    Text(String),
+
+    /// Comment node
+    Comment(String),
+
+    /// Doctype node
+    Doctype(Doctype),
+
+    /// Document node, with its quirks mode
+    Document(QuirksMode),
+
+    /// Document fragment node
+    DocumentFragment,
+}
+
+impl NodeRef {
+    /// Return an owned copy of this node and its descendants
+    /// that can be sent to another thread.
+    ///
+    /// Other references to nodes of this tree are not affected.
+    pub fn into_sendable(self) -> SendableNode {
+        // Use an explicit stack of ancestors rather than recursion,
+        // for the same reason as in `impl Drop for Node`.
+        let mut stack: Vec<SendableNode> = Vec::new();
+        for edge in self.traverse_inclusive() {
+            match edge {
+                NodeEdge::Start(node) => stack.push(SendableNode {
+                    data: SendableNodeData::new(node.data()),
+                    children: Vec::new(),
+                }),
+                NodeEdge::End(_) => {
+                    let node = stack.pop().unwrap();
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => return node
+                    }
+                }
+            }
+        }
+        unreachable!()
+    }
+}
+
+impl SendableNode {
+    /// Build a new tree from this copy, and return its root.
+    pub fn into_node(self) -> NodeRef {
+        let SendableNode { data, children } = self;
+        let root = data.into_node();
+        let mut stack = vec![(root.clone(), children.into_iter())];
+        loop {
+            let next_child = match stack.last_mut() {
+                Some(&mut (_, ref mut children)) => children.next(),
+                None => break
+            };
+            match next_child {
+                Some(SendableNode { data, children }) => {
+                    let node = data.into_node();
+                    stack.last().unwrap().0.append(node.clone());
+                    stack.push((node, children.into_iter()));
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        root
+    }
+}
+
+impl SendableNodeData {
+    fn new(data: &NodeData) -> SendableNodeData {
+        match *data {
+            NodeData::Element(ref element) => SendableNodeData::Element {
+                name: element.name.clone(),
+                attributes: element.attributes.borrow().list.clone(),
+                template_contents: element.template_contents.as_ref().map(|contents| {
+                    Box::new(contents.clone().into_sendable())
+                }),
+            },
+            NodeData::Text(ref text) => SendableNodeData::Text(text.borrow().clone()),
+            NodeData::Comment(ref text) => SendableNodeData::Comment(text.borrow().clone()),
+            NodeData::Doctype(ref doctype) => SendableNodeData::Doctype(doctype.clone()),
+            NodeData::Document(ref document) => SendableNodeData::Document(document.quirks_mode()),
+            NodeData::DocumentFragment => SendableNodeData::DocumentFragment,
+        }
+    }
+
+    fn into_node(self) -> NodeRef {
+        match self {
+            SendableNodeData::Element { name, attributes, template_contents } => {
+                NodeRef::new(NodeData::Element(ElementData {
+                    name: name,
+                    attributes: RefCell::new(Attributes { list: attributes }),
+                    template_contents: template_contents.map(|contents| contents.into_node()),
+                }))
+            }
+            SendableNodeData::Text(text) => NodeRef::new_text(text),
+            SendableNodeData::Comment(text) => NodeRef::new_comment(text),
+            SendableNodeData::Doctype(doctype) => NodeRef::new(NodeData::Doctype(doctype)),
+            SendableNodeData::Document(quirks_mode) => {
+                let document = NodeRef::new_document();
+                document.as_document().unwrap().set_quirks_mode(quirks_mode);
+                document
+            }
+            SendableNodeData::DocumentFragment => NodeRef::new(NodeData::DocumentFragment),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+extern crate serde;
+
+use html5ever::tree_builder::QuirksMode;
+use self::serde::{Serialize, Serializer, Deserialize, Deserializer};
+use self::serde::de::{Error, MapVisitor, Visitor};
+use std::cell::RefCell;
+use string_cache::{Atom, Namespace, QualName};
+
+use tree::{NodeRef, NodeData, ElementData, Doctype};
+
+/// Serialize this node and its descendants as a tree of maps,
+/// for example to JSON with `serde_json`.
+///
+/// Each node is a map with a `"type"` key and other keys depending on the type:
+///
+/// * `"document"`: `"quirks_mode"` (`"no-quirks"`, `"limited-quirks"`, or `"quirks"`)
+///   and `"children"`
+/// * `"document-fragment"`: `"children"`
+/// * `"doctype"`: `"name"`, `"public_id"`, and `"system_id"`
+/// * `"element"`: `"namespace"` (a URL, or an empty string for no namespace), `"name"`,
+///   `"attributes"`, `"children"`, and for template elements `"template_contents"`
+/// * `"text"` and `"comment"`: `"data"`
+///
+/// `"children"` and `"template_contents"` are lists of nodes.
+/// `"attributes"` is a list of maps with `"namespace"`, `"name"`, and `"value"` keys,
+/// in the order of `Attributes::list`.
+/// Source positions and other caches are not serialized.
+///
+/// For example, `<p class=a>Hi</p>` is serialized to JSON as:
+///
+/// ```json
+/// {"type":"element","namespace":"http://www.w3.org/1999/xhtml","name":"p",
+///  "attributes":[{"namespace":"","name":"class","value":"a"}],
+///  "children":[{"type":"text","data":"Hi"}]}
+/// ```
+///
+/// Nesting follows the tree, so very deep trees may exhaust the stack.
+impl Serialize for NodeRef {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        let mut state = try!(serializer.serialize_map(None));
+        match *self.data() {
+            NodeData::Document(ref document) => {
+                try!(entry(serializer, &mut state, "type", "document"));
+                let quirks_mode = match document.quirks_mode() {
+                    QuirksMode::NoQuirks => "no-quirks",
+                    QuirksMode::LimitedQuirks => "limited-quirks",
+                    QuirksMode::Quirks => "quirks",
+                };
+                try!(entry(serializer, &mut state, "quirks_mode", quirks_mode));
+                try!(entry(serializer, &mut state, "children", Children(self)));
+            }
+            NodeData::DocumentFragment => {
+                try!(entry(serializer, &mut state, "type", "document-fragment"));
+                try!(entry(serializer, &mut state, "children", Children(self)));
+            }
+            NodeData::Doctype(ref doctype) => {
+                try!(entry(serializer, &mut state, "type", "doctype"));
+                try!(entry(serializer, &mut state, "name", &doctype.name));
+                try!(entry(serializer, &mut state, "public_id", &doctype.public_id));
+                try!(entry(serializer, &mut state, "system_id", &doctype.system_id));
+            }
+            NodeData::Element(ref element) => {
+                try!(entry(serializer, &mut state, "type", "element"));
+                try!(entry(serializer, &mut state, "namespace", &*element.name.ns.0));
+                try!(entry(serializer, &mut state, "name", &*element.name.local));
+                let attributes = element.attributes.borrow();
+                try!(entry(serializer, &mut state, "attributes",
+                           attributes.list.iter().map(|&(ref name, ref value)| {
+                               SerializedAttribute(name, value)
+                           }).collect::<Vec<_>>()));
+                try!(entry(serializer, &mut state, "children", Children(self)));
+                if let Some(ref contents) = element.template_contents {
+                    try!(entry(serializer, &mut state, "template_contents", Children(contents)));
+                }
+            }
+            NodeData::Text(ref text) => {
+                try!(entry(serializer, &mut state, "type", "text"));
+                try!(entry(serializer, &mut state, "data", &*text.borrow()));
+            }
+            NodeData::Comment(ref text) => {
+                try!(entry(serializer, &mut state, "type", "comment"));
+                try!(entry(serializer, &mut state, "data", &*text.borrow()));
+            }
+        }
+        serializer.serialize_map_end(state)
+    }
+}
+
+fn entry<S: Serializer, V: Serialize>(serializer: &mut S, state: &mut S::MapState,
+                                      key: &str, value: V) -> Result<(), S::Error> {
+    try!(serializer.serialize_map_key(state, key));
+    serializer.serialize_map_value(state, value)
+}
+
+/// The children of a node, serialized as a list.
+struct Children<'a>(&'a NodeRef);
+
+impl<'a> Serialize for Children<'a> {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        let mut state = try!(serializer.serialize_seq(None));
+        for child in self.0.children() {
+            try!(serializer.serialize_seq_elt(&mut state, child));
+        }
+        serializer.serialize_seq_end(state)
+    }
+}
+
+struct SerializedAttribute<'a>(&'a QualName, &'a String);
+
+impl<'a> Serialize for SerializedAttribute<'a> {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        let mut state = try!(serializer.serialize_map(Some(3)));
+        try!(entry(serializer, &mut state, "namespace", &*(self.0).ns.0));
+        try!(entry(serializer, &mut state, "name", &*(self.0).local));
+        try!(entry(serializer, &mut state, "value", self.1));
+        serializer.serialize_map_end(state)
+    }
+}
+
+/// Build a new tree from the format written by `impl Serialize for NodeRef`.
+///
+/// The returned node has no parent.
+/// Unknown keys and missing required keys are errors.
+impl Deserialize for NodeRef {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<NodeRef, D::Error> {
+        deserializer.deserialize_map(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl Visitor for NodeVisitor {
+    type Value = NodeRef;
+
+    fn visit_map<V: MapVisitor>(&mut self, mut visitor: V) -> Result<NodeRef, V::Error> {
+        let mut type_ = None;
+        let mut quirks_mode = None;
+        let mut name = None;
+        let mut namespace = None;
+        let mut public_id = None;
+        let mut system_id = None;
+        let mut attributes = None;
+        let mut children = None;
+        let mut template_contents = None;
+        let mut data = None;
+        while let Some(key) = try!(visitor.visit_key::<String>()) {
+            match &*key {
+                "type" => type_ = Some(try!(visitor.visit_value::<String>())),
+                "quirks_mode" => quirks_mode = Some(try!(visitor.visit_value::<String>())),
+                "name" => name = Some(try!(visitor.visit_value::<String>())),
+                "namespace" => namespace = Some(try!(visitor.visit_value::<String>())),
+                "public_id" => public_id = Some(try!(visitor.visit_value::<String>())),
+                "system_id" => system_id = Some(try!(visitor.visit_value::<String>())),
+                "attributes" => {
+                    attributes = Some(try!(visitor.visit_value::<Vec<DeserializedAttribute>>()))
+                }
+                "children" => children = Some(try!(visitor.visit_value::<Vec<NodeRef>>())),
+                "template_contents" => {
+                    template_contents = Some(try!(visitor.visit_value::<Vec<NodeRef>>()))
+                }
+                "data" => data = Some(try!(visitor.visit_value::<String>())),
+                _ => return Err(V::Error::unknown_field(&key)),
+            }
+        }
+        try!(visitor.end());
+
+        let type_ = try!(type_.ok_or(V::Error::missing_field("type")));
+        let node = match &*type_ {
+            "document" => {
+                let node = NodeRef::new_document();
+                let quirks_mode = match quirks_mode.as_ref().map(|mode| &**mode) {
+                    None | Some("no-quirks") => QuirksMode::NoQuirks,
+                    Some("limited-quirks") => QuirksMode::LimitedQuirks,
+                    Some("quirks") => QuirksMode::Quirks,
+                    Some(_) => return Err(V::Error::invalid_value("unknown quirks mode")),
+                };
+                node.as_document().unwrap().set_quirks_mode(quirks_mode);
+                node
+            }
+            "document-fragment" => NodeRef::new(NodeData::DocumentFragment),
+            "doctype" => NodeRef::new(NodeData::Doctype(Doctype {
+                name: try!(name.ok_or(V::Error::missing_field("name"))),
+                public_id: public_id.unwrap_or_default(),
+                system_id: system_id.unwrap_or_default(),
+            })),
+            "element" => {
+                let name = QualName::new(
+                    Namespace(Atom::from(&*namespace.unwrap_or_default())),
+                    Atom::from(&*try!(name.ok_or(V::Error::missing_field("name")))));
+                let is_template = name == qualname!(html, "template");
+                let attributes = attributes.unwrap_or_default().into_iter().map(|attribute| {
+                    (attribute.name, attribute.value)
+                });
+                let node = NodeRef::new(NodeData::Element(ElementData {
+                    name: name,
+                    attributes: RefCell::new(attributes.collect()),
+                    template_contents: if is_template || template_contents.is_some() {
+                        Some(NodeRef::new(NodeData::DocumentFragment))
+                    } else {
+                        None
+                    },
+                }));
+                if let Some(contents) = node.as_element().unwrap().template_contents.as_ref() {
+                    contents.append_children(template_contents.unwrap_or_default())
+                }
+                node
+            }
+            "text" => NodeRef::new_text(try!(data.ok_or(V::Error::missing_field("data")))),
+            "comment" => NodeRef::new_comment(try!(data.ok_or(V::Error::missing_field("data")))),
+            _ => return Err(V::Error::invalid_value("unknown node type")),
+        };
+        node.append_children(children.unwrap_or_default());
+        Ok(node)
+    }
+}
+
+struct DeserializedAttribute {
+    name: QualName,
+    value: String,
+}
+
+impl Deserialize for DeserializedAttribute {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<DeserializedAttribute, D::Error> {
+        deserializer.deserialize_map(AttributeVisitor)
+    }
+}
+
+struct AttributeVisitor;
+
+impl Visitor for AttributeVisitor {
+    type Value = DeserializedAttribute;
+
+    fn visit_map<V: MapVisitor>(&mut self, mut visitor: V)
+                                -> Result<DeserializedAttribute, V::Error> {
+        let mut namespace = None;
+        let mut name = None;
+        let mut value = None;
+        while let Some(key) = try!(visitor.visit_key::<String>()) {
+            match &*key {
+                "namespace" => namespace = Some(try!(visitor.visit_value::<String>())),
+                "name" => name = Some(try!(visitor.visit_value::<String>())),
+                "value" => value = Some(try!(visitor.visit_value::<String>())),
+                _ => return Err(V::Error::unknown_field(&key)),
+            }
+        }
+        try!(visitor.end());
+        let name = try!(name.ok_or(V::Error::missing_field("name")));
+        Ok(DeserializedAttribute {
+            name: QualName::new(Namespace(Atom::from(&*namespace.unwrap_or_default())),
+                                Atom::from(&*name)),
+            value: try!(value.ok_or(V::Error::missing_field("value"))),
+        })
+    }
+}
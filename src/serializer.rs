@@ -1,10 +1,16 @@
+use std::collections::HashSet;
+use std::fmt;
 use std::fs::File;
-use std::io::{Write, Result};
+use std::io::{self, Write, Result};
 use std::path::Path;
-use std::string::ToString;
-use html5ever::serialize::{Serializable, Serializer, TraversalScope, serialize, SerializeOpts};
+use std::rc::Rc;
+use std::str;
+use html5ever::serialize::{Serializable, Serializer, TraversalScope};
 use html5ever::serialize::TraversalScope::*;
+use selectors::matching::SELECTOR_WHITESPACE;
+use string_cache::{Atom, Namespace, QualName};
 
+use iter::NodeEdge;
 use tree::{NodeRef, NodeData};
 
 
@@ -16,7 +22,12 @@ impl Serializable for NodeRef {
                 if traversal_scope == IncludeNode {
                     try!(serializer.start_elem(
                         element.name.clone(),
-                        element.attributes.borrow().map.iter().map(|(name, value)| (name, &**value))));
+                        element.attributes.borrow().list.iter()
+                            .map(|&(ref name, ref value)| (name, &**value))));
+                }
+
+                if let Some(ref contents) = element.template_contents {
+                    try!(Serializable::serialize(contents, serializer, ChildrenOnly));
                 }
 
                 for child in self.children() {
@@ -47,23 +58,703 @@ impl Serializable for NodeRef {
 }
 
 
-impl ToString for NodeRef {
-    #[inline]
-    fn to_string(&self) -> String {
-        let mut u8_vec = Vec::new();
-        self.serialize(&mut u8_vec).unwrap();
-        String::from_utf8(u8_vec).unwrap()
+/// Options for `NodeRef::serialize_with_options`.
+#[derive(Clone)]
+pub struct SerializeOpts {
+    /// When `Some(n)`, pretty-print: put child nodes on separate lines,
+    /// indented by `n` spaces per level of nesting.
+    ///
+    /// Only the children of elements that contain nothing but other elements,
+    /// comments, and whitespace are laid out this way.
+    /// Elements containing text or inline elements are written on one line as usual,
+    /// and the contents of elements like `<pre>` or `<script>` are never changed.
+    ///
+    /// The default is `None`: compact output that keeps whitespace as it is in the tree.
+    pub indent: Option<usize>,
+
+    /// The local names of elements that are laid out inline with text, for pretty-printing.
+    ///
+    /// The default is the set of HTML phrasing content elements, such as `span` or `img`.
+    pub inline_elements: HashSet<Atom>,
+
+    /// Whether to write non-ASCII characters in text and attribute values
+    /// as numeric character references like `&#xE9;`, rather than as UTF-8.
+    ///
+    /// In HTML syntax, U+00A0 is always written as `&nbsp;`.
+    /// The contents of comments and of elements like `<script>` are not escaped,
+    /// since that would change their meaning.
+    /// The default is `false`.
+    pub escape_non_ascii: bool,
+
+    /// Whether to write `'` in attribute values as `&#39;`. The default is `false`.
+    pub escape_apostrophes: bool,
+
+    /// Whether to use XML syntax rather than HTML syntax. The default is `false`.
+    ///
+    /// In XML syntax, elements without children are written as self-closing tags like `<br/>`,
+    /// and there is no special case for void elements or for the contents of `<script>`.
+    /// Elements in the SVG and MathML namespaces have `svg:` and `math:` prefixes,
+    /// other elements use the default namespace.
+    /// Namespace declarations are added where needed,
+    /// and replace `xmlns` attributes from the tree.
+    pub xml: bool,
+
+    /// A function called with the name of an element and the name of one of its attributes,
+    /// that returns whether to write that attribute.
+    /// Omitted attributes are left in the tree.
+    ///
+    /// For example, to leave out event handler attributes like `onclick`:
+    ///
+    /// ```rust
+    /// # use std::rc::Rc;
+    /// let mut opts = kuchiki::SerializeOpts::default();
+    /// opts.attribute_filter = Some(Rc::new(|_element, attribute| {
+    ///     !attribute.local.starts_with("on")
+    /// }));
+    /// ```
+    ///
+    /// This is a shared `Fn` rather than `FnMut` so that `SerializeOpts` stays `Clone`.
+    /// A filter that needs mutable state can keep it in a `Cell` or `RefCell`.
+    ///
+    /// The default is `None`: all attributes are written.
+    pub attribute_filter: Option<Rc<Fn(&QualName, &QualName) -> bool>>,
+
+    /// Whether to leave out end tags that the HTML syntax allows to omit,
+    /// where the HTML parser would produce the same tree without them.
+    ///
+    /// This covers the end tags of `<li>`, `<dt>`, `<dd>`, `<p>`, `<option>`, `<optgroup>`,
+    /// `<tr>`, `<td>`, `<th>`, `<thead>`, `<tbody>`, `<tfoot>`, `<colgroup>`, `<caption>`,
+    /// `<html>`, `<head>`, and `<body>`, depending on what follows them.
+    /// A `</p>` followed by a `<table>` is kept, since it is only optional in no-quirks mode.
+    /// End tags are not omitted when pretty-printing (with `indent`),
+    /// or for the node that serialization starts from.
+    ///
+    /// This is ignored in XML syntax. The default is `false`.
+    pub omit_optional_end_tags: bool,
+}
+
+impl fmt::Debug for SerializeOpts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SerializeOpts")
+            .field("indent", &self.indent)
+            .field("inline_elements", &self.inline_elements)
+            .field("escape_non_ascii", &self.escape_non_ascii)
+            .field("escape_apostrophes", &self.escape_apostrophes)
+            .field("xml", &self.xml)
+            .field("attribute_filter", &self.attribute_filter.as_ref().map(|_| "Fn"))
+            .field("omit_optional_end_tags", &self.omit_optional_end_tags)
+            .finish()
+    }
+}
+
+impl Default for SerializeOpts {
+    fn default() -> SerializeOpts {
+        SerializeOpts {
+            indent: None,
+            inline_elements: default_inline_elements(),
+            escape_non_ascii: false,
+            escape_apostrophes: false,
+            xml: false,
+            attribute_filter: None,
+            omit_optional_end_tags: false,
+        }
+    }
+}
+
+/// The local names of HTML phrasing content elements.
+pub fn default_inline_elements() -> HashSet<Atom> {
+    [
+        "a", "abbr", "audio", "b", "bdi", "bdo", "big", "br", "button", "canvas",
+        "cite", "code", "data", "datalist", "del", "dfn", "em", "embed", "font", "i",
+        "iframe", "img", "input", "ins", "kbd", "label", "map", "mark", "math", "meter",
+        "nobr", "object", "output", "picture", "progress", "q", "ruby", "s", "samp",
+        "select", "small", "span", "strike", "strong", "sub", "sup", "svg", "textarea",
+        "time", "tt", "u", "var", "video", "wbr",
+    ].iter().map(|&name| Atom::from(name)).collect()
+}
+
+
+/// Information about a node whose descendants are being serialized.
+struct ElemInfo {
+    /// The local name, for elements in the HTML namespace.
+    html_name: Option<Atom>,
+    /// Whether this node and its descendants are not written at all.
+    skipped: bool,
+    /// Whether the descendants of this node are not written, as for void elements.
+    ignore_children: bool,
+    /// Whether pretty-printing is disabled for this node’s descendants.
+    preserve_whitespace: bool,
+    /// Whether this node’s children are put on separate lines.
+    pretty_children: bool,
+    /// Whether a child of this node was written on a separate line.
+    wrote_pretty_child: bool,
+    /// The indentation level of this node’s children.
+    child_depth: usize,
+    /// In XML syntax, the default namespace in scope for this node’s children.
+    xml_default_ns: Namespace,
+    /// In XML syntax, the namespaces whose prefixes are declared for this node’s children.
+    xml_prefixed_ns: Vec<Namespace>,
+}
+
+/// An HTML serializer that traverses the tree without recursion.
+struct HtmlSerializer<'a, W: 'a + Write> {
+    writer: &'a mut W,
+    opts: &'a SerializeOpts,
+    stack: Vec<ElemInfo>,
+    at_line_start: bool,
+    /// The node that serialization started from, if it is written itself.
+    root: Option<NodeRef>,
+}
+
+impl<'a, W: Write> HtmlSerializer<'a, W> {
+    fn new(writer: &'a mut W, opts: &'a SerializeOpts) -> HtmlSerializer<'a, W> {
+        HtmlSerializer {
+            writer: writer,
+            opts: opts,
+            stack: Vec::new(),
+            at_line_start: true,
+            root: None,
+        }
+    }
+
+    fn serialize(mut self, node: &NodeRef, traversal_scope: TraversalScope) -> Result<()> {
+        let mut edges = match traversal_scope {
+            IncludeNode => {
+                self.stack.push(ElemInfo {
+                    html_name: None,
+                    skipped: false,
+                    ignore_children: false,
+                    preserve_whitespace: false,
+                    pretty_children: self.opts.indent.is_some(),
+                    wrote_pretty_child: false,
+                    child_depth: 0,
+                    xml_default_ns: ns!(),
+                    xml_prefixed_ns: Vec::new(),
+                });
+                self.root = Some(node.clone());
+                vec![node.traverse_inclusive()]
+            }
+            ChildrenOnly => {
+                let mut info = self.elem_info(node);
+                // This node’s own tag and namespace declarations are not written.
+                info.child_depth = 0;
+                info.xml_default_ns = ns!();
+                info.xml_prefixed_ns.clear();
+                self.stack.push(info);
+                let mut edges = vec![node.traverse()];
+                if let Some(contents) = template_contents(node) {
+                    edges.push(contents.traverse())
+                }
+                edges
+            }
+        };
+        // Template contents are not children of the `<template>` element,
+        // but are serialized as if they were (before any actual children).
+        // Their traversal is pushed on top of the one that will produce the element’s end.
+        loop {
+            let edge = match edges.last_mut() {
+                Some(traverse) => traverse.next(),
+                None => return Ok(())
+            };
+            match edge {
+                Some(NodeEdge::Start(node)) => {
+                    try!(self.start(&node));
+                    if let Some(contents) = template_contents(&node) {
+                        edges.push(contents.traverse())
+                    }
+                }
+                Some(NodeEdge::End(node)) => try!(self.end(&node)),
+                None => {
+                    edges.pop();
+                }
+            }
+        }
+    }
+
+    /// Compute information about a node, based on that of its parent (the top of the stack).
+    fn elem_info(&self, node: &NodeRef) -> ElemInfo {
+        let (parent_preserve_whitespace, depth) = match self.stack.last() {
+            Some(parent) => (parent.preserve_whitespace, parent.child_depth),
+            None => (false, 0),
+        };
+        let (mut xml_default_ns, mut xml_prefixed_ns) = match self.stack.last() {
+            Some(parent) => (parent.xml_default_ns.clone(), parent.xml_prefixed_ns.clone()),
+            None => (ns!(), Vec::new()),
+        };
+        let (html_name, ignore_children, child_depth) = match *node.data() {
+            NodeData::Element(ref element) => {
+                let html_name = if element.name.ns == ns!(html) {
+                    Some(element.name.local.clone())
+                } else {
+                    None
+                };
+                if self.opts.xml {
+                    let attributes = element.attributes.borrow();
+                    let attribute_namespaces = attributes.list.iter()
+                        .filter(|&&(ref name, _)| self.writes_attribute(&element.name, name))
+                        .map(|&(ref name, _)| &name.ns);
+                    for ns in Some(&element.name.ns).into_iter().chain(attribute_namespaces) {
+                        if xml_prefix(ns).is_some() {
+                            if !xml_prefixed_ns.contains(ns) {
+                                xml_prefixed_ns.push(ns.clone())
+                            }
+                        } else if *ns == element.name.ns {
+                            xml_default_ns = ns.clone()
+                        }
+                    }
+                }
+                let void = !self.opts.xml && html_name.as_ref().map_or(false, is_void_element);
+                (html_name, void, depth + 1)
+            }
+            NodeData::Document(_) | NodeData::DocumentFragment => (None, false, depth),
+            _ => (None, true, depth),
+        };
+        let preserve_whitespace = parent_preserve_whitespace ||
+            html_name.as_ref().map_or(false, preserves_whitespace);
+        let contents = template_contents(node);
+        let pretty_children = self.opts.indent.is_some() && !preserve_whitespace &&
+            contents.iter().flat_map(NodeRef::children).chain(node.children()).all(|child| {
+                match *child.data() {
+                    NodeData::Text(ref text) => is_whitespace(&text.borrow()),
+                    NodeData::Element(ref element) => {
+                        !self.opts.inline_elements.contains(&element.name.local)
+                    }
+                    _ => true,
+                }
+            });
+        ElemInfo {
+            html_name: html_name,
+            skipped: false,
+            ignore_children: ignore_children,
+            preserve_whitespace: preserve_whitespace,
+            pretty_children: pretty_children,
+            wrote_pretty_child: false,
+            child_depth: child_depth,
+            xml_default_ns: xml_default_ns,
+            xml_prefixed_ns: xml_prefixed_ns,
+        }
+    }
+
+    fn start(&mut self, node: &NodeRef) -> Result<()> {
+        let mut info = self.elem_info(node);
+        let (parent_ignores_children, parent_pretty) = {
+            let parent = self.stack.last().unwrap();
+            (parent.ignore_children || parent.skipped, parent.pretty_children)
+        };
+        let skip_whitespace = parent_pretty && node.as_text().map_or(false, |text| {
+            is_whitespace(&text.borrow())
+        });
+        if parent_ignores_children || skip_whitespace {
+            info.skipped = true;
+            self.stack.push(info);
+            return Ok(())
+        }
+        if parent_pretty {
+            let depth = self.stack.last().unwrap().child_depth;
+            self.stack.last_mut().unwrap().wrote_pretty_child = true;
+            try!(self.newline(depth));
+        }
+        self.stack.push(info);
+
+        match *node.data() {
+            NodeData::Element(ref element) => {
+                try!(self.writer.write_all(b"<"));
+                try!(self.write_element_name(&element.name));
+                if self.opts.xml {
+                    try!(self.write_namespace_declarations());
+                }
+                for &(ref name, ref value) in &element.attributes.borrow().list {
+                    if self.opts.xml && name.ns == ns!(xmlns) {
+                        continue
+                    }
+                    if !self.writes_attribute(&element.name, name) {
+                        continue
+                    }
+                    try!(self.writer.write_all(b" "));
+                    try!(self.write_attribute_name(name));
+                    try!(self.writer.write_all(b"=\""));
+                    try!(self.write_escaped(value, true));
+                    try!(self.writer.write_all(b"\""));
+                }
+                if self.opts.xml && has_no_children(node) {
+                    try!(self.writer.write_all(b"/>"));
+                } else {
+                    try!(self.writer.write_all(b">"));
+                }
+            }
+            NodeData::Text(ref text) => {
+                let text = text.borrow();
+                let parent = &self.stack[self.stack.len() - 2];
+                let raw = !self.opts.xml && match parent.html_name {
+                    Some(atom!("style")) | Some(atom!("script")) | Some(atom!("xmp"))
+                    | Some(atom!("iframe")) | Some(atom!("noembed")) | Some(atom!("noframes"))
+                    | Some(atom!("plaintext")) | Some(atom!("noscript")) => true,
+                    _ => false,
+                };
+                let prepend_lf = !self.opts.xml && text.starts_with("\n") &&
+                    node.previous_sibling().is_none() &&
+                    match parent.html_name {
+                        Some(atom!("pre")) | Some(atom!("textarea")) | Some(atom!("listing")) => true,
+                        _ => false,
+                    };
+                if prepend_lf {
+                    try!(self.writer.write_all(b"\n"));
+                }
+                if raw {
+                    try!(self.writer.write_all(text.as_bytes()));
+                } else {
+                    try!(self.write_escaped(&text, false));
+                }
+            }
+            NodeData::Comment(ref text) => {
+                try!(self.writer.write_all(b"<!--"));
+                try!(self.writer.write_all(text.borrow().as_bytes()));
+                try!(self.writer.write_all(b"-->"));
+            }
+            NodeData::Doctype(ref doctype) => {
+                try!(self.writer.write_all(b"<!DOCTYPE "));
+                try!(self.writer.write_all(doctype.name.as_bytes()));
+                if !doctype.public_id.is_empty() {
+                    try!(write!(self.writer, " PUBLIC \"{}\"", doctype.public_id));
+                    if !doctype.system_id.is_empty() {
+                        try!(write!(self.writer, " \"{}\"", doctype.system_id));
+                    }
+                } else if !doctype.system_id.is_empty() {
+                    try!(write!(self.writer, " SYSTEM \"{}\"", doctype.system_id));
+                }
+                try!(self.writer.write_all(b">\n"));
+                self.at_line_start = true;
+                return Ok(())
+            }
+            NodeData::Document(_) | NodeData::DocumentFragment => return Ok(())
+        }
+        self.at_line_start = false;
+        Ok(())
+    }
+
+    fn end(&mut self, node: &NodeRef) -> Result<()> {
+        let info = self.stack.pop().unwrap();
+        if info.skipped || info.ignore_children {
+            return Ok(())
+        }
+        if let Some(element) = node.as_element() {
+            if self.opts.xml && has_no_children(node) {
+                return Ok(())
+            }
+            if self.omits_end_tag(node, &info) {
+                return Ok(())
+            }
+            if info.wrote_pretty_child {
+                try!(self.newline(info.child_depth - 1));
+            }
+            try!(self.writer.write_all(b"</"));
+            try!(self.write_element_name(&element.name));
+            try!(self.writer.write_all(b">"));
+            self.at_line_start = false;
+        }
+        Ok(())
+    }
+
+    /// Whether to leave out the end tag of this node (after its info was popped from the stack).
+    fn omits_end_tag(&self, node: &NodeRef, info: &ElemInfo) -> bool {
+        let local_name = match info.html_name {
+            Some(ref name) if self.opts.omit_optional_end_tags && !self.opts.xml => name,
+            _ => return false
+        };
+        if self.stack.last().map_or(true, |parent| parent.pretty_children) ||
+                self.root.as_ref() == Some(node) {
+            return false
+        }
+        end_tag_is_optional(node, local_name)
+    }
+
+    fn writes_attribute(&self, element_name: &QualName, attribute_name: &QualName) -> bool {
+        self.opts.attribute_filter.as_ref().map_or(true, |filter| filter(element_name, attribute_name))
+    }
+
+    fn newline(&mut self, depth: usize) -> Result<()> {
+        if !self.at_line_start {
+            try!(self.writer.write_all(b"\n"));
+        }
+        let spaces = depth * self.opts.indent.unwrap_or(0);
+        for _ in 0..spaces {
+            try!(self.writer.write_all(b" "));
+        }
+        self.at_line_start = spaces == 0;
+        Ok(())
+    }
+
+    fn write_element_name(&mut self, name: &QualName) -> Result<()> {
+        if self.opts.xml {
+            if let Some(prefix) = xml_prefix(&name.ns) {
+                try!(self.writer.write_all(prefix.as_bytes()));
+                try!(self.writer.write_all(b":"));
+            }
+        }
+        self.writer.write_all(name.local.as_bytes())
+    }
+
+    /// Declare the namespaces that are in scope for the element at the top of the stack,
+    /// but not for its parent.
+    fn write_namespace_declarations(&mut self) -> Result<()> {
+        let info = &self.stack[self.stack.len() - 1];
+        let parent = &self.stack[self.stack.len() - 2];
+        if info.xml_default_ns != parent.xml_default_ns {
+            try!(self.writer.write_all(b" xmlns=\""));
+            try!(self.writer.write_all(info.xml_default_ns.0.as_bytes()));
+            try!(self.writer.write_all(b"\""));
+        }
+        for ns in &info.xml_prefixed_ns[parent.xml_prefixed_ns.len()..] {
+            try!(self.writer.write_all(b" xmlns:"));
+            try!(self.writer.write_all(xml_prefix(ns).unwrap().as_bytes()));
+            try!(self.writer.write_all(b"=\""));
+            try!(self.writer.write_all(ns.0.as_bytes()));
+            try!(self.writer.write_all(b"\""));
+        }
+        Ok(())
+    }
+
+    fn write_attribute_name(&mut self, name: &QualName) -> Result<()> {
+        match name.ns {
+            ns!() => {}
+            ns!(xml) => try!(self.writer.write_all(b"xml:")),
+            ns!(xmlns) => {
+                if name.local != atom!("xmlns") {
+                    try!(self.writer.write_all(b"xmlns:"));
+                }
+            }
+            ns!(xlink) => try!(self.writer.write_all(b"xlink:")),
+            ref ns => match xml_prefix(ns) {
+                Some(prefix) if self.opts.xml => {
+                    try!(self.writer.write_all(prefix.as_bytes()));
+                    try!(self.writer.write_all(b":"));
+                }
+                _ => try!(self.writer.write_all(b"unknown_namespace:")),
+            },
+        }
+        self.writer.write_all(name.local.as_bytes())
+    }
+
+    fn write_escaped(&mut self, text: &str, attr_mode: bool) -> Result<()> {
+        let mut last_end = 0;
+        for (i, c) in text.char_indices() {
+            let escaped: &[u8] = match c {
+                '&' => b"&amp;",
+                '\u{A0}' if !self.opts.xml => b"&nbsp;",
+                '"' if attr_mode => b"&quot;",
+                '<' if !attr_mode || self.opts.xml => b"&lt;",
+                '>' if !attr_mode || self.opts.xml => b"&gt;",
+                '\'' if attr_mode && self.opts.escape_apostrophes => b"&#39;",
+                _ if c > '\u{7F}' && self.opts.escape_non_ascii => {
+                    try!(self.writer.write_all(text[last_end..i].as_bytes()));
+                    try!(write!(self.writer, "&#x{:X};", c as u32));
+                    last_end = i + c.len_utf8();
+                    continue
+                }
+                _ => continue,
+            };
+            try!(self.writer.write_all(text[last_end..i].as_bytes()));
+            try!(self.writer.write_all(escaped));
+            last_end = i + c.len_utf8();
+        }
+        self.writer.write_all(text[last_end..].as_bytes())
+    }
+}
+
+/// The prefix used in XML syntax for elements and attributes in a namespace, if any.
+fn xml_prefix(ns: &Namespace) -> Option<&'static str> {
+    match *ns {
+        ns!(svg) => Some("svg"),
+        ns!(mathml) => Some("math"),
+        ns!(xlink) => Some("xlink"),
+        _ => None,
+    }
+}
+
+fn is_void_element(local_name: &Atom) -> bool {
+    match *local_name {
+        atom!("area") | atom!("base") | atom!("basefont") | atom!("bgsound") | atom!("br")
+        | atom!("col") | atom!("embed") | atom!("frame") | atom!("hr") | atom!("img")
+        | atom!("input") | atom!("keygen") | atom!("link") | atom!("menuitem")
+        | atom!("meta") | atom!("param") | atom!("source") | atom!("track") | atom!("wbr")
+            => true,
+        _ => false,
+    }
+}
+
+/// Whether whitespace in this HTML element is significant,
+/// so that pretty-printing must not change it.
+pub fn preserves_whitespace(local_name: &Atom) -> bool {
+    match *local_name {
+        atom!("pre") | atom!("textarea") | atom!("listing") | atom!("plaintext")
+        | atom!("script") | atom!("style") | atom!("xmp") | atom!("iframe")
+        | atom!("noembed") | atom!("noframes") | atom!("noscript")
+            => true,
+        _ => false,
+    }
+}
+
+/// Whether the end tag of this HTML element can be omitted,
+/// based on the HTML syntax’s optional tags rules.
+fn end_tag_is_optional(node: &NodeRef, local_name: &Atom) -> bool {
+    let next = node.next_sibling();
+    let is_last = next.is_none();
+    let next_name = next.as_ref().and_then(|next| next.as_element()).and_then(|element| {
+        if element.name.ns == ns!(html) { Some(element.name.local.clone()) } else { None }
+    });
+    let next_is_comment = next.as_ref().map_or(false, |next| next.as_comment().is_some());
+    let next_is_space_or_comment = next_is_comment || next.as_ref().map_or(false, |next| {
+        next.as_text().map_or(false, |text| text.borrow().starts_with(SELECTOR_WHITESPACE))
+    });
+    let parent_name = node.parent().and_then(|parent| parent.as_element().and_then(|element| {
+        if element.name.ns == ns!(html) { Some(element.name.local.clone()) } else { None }
+    }));
+    let followed_by = |names: &[Atom]| next_name.as_ref().map_or(false, |name| names.contains(name));
+    let last_in = |names: &[Atom]| {
+        is_last && parent_name.as_ref().map_or(false, |name| names.contains(name))
+    };
+    match *local_name {
+        atom!("html") | atom!("body") => !next_is_comment,
+        atom!("head") | atom!("colgroup") | atom!("caption") => !next_is_space_or_comment,
+        atom!("li") => followed_by(&[atom!("li")]) ||
+            last_in(&[atom!("ul"), atom!("ol"), atom!("menu")]),
+        atom!("dt") => followed_by(&[atom!("dt"), atom!("dd")]),
+        atom!("dd") => followed_by(&[atom!("dt"), atom!("dd")]) || last_in(&[atom!("dl")]),
+        atom!("option") => followed_by(&[atom!("option"), atom!("optgroup")]) ||
+            last_in(&[atom!("select"), atom!("datalist"), atom!("optgroup")]),
+        atom!("optgroup") => followed_by(&[atom!("optgroup")]) || last_in(&[atom!("select")]),
+        atom!("td") | atom!("th") => followed_by(&[atom!("td"), atom!("th")]) ||
+            last_in(&[atom!("tr")]),
+        atom!("tr") => followed_by(&[atom!("tr")]) ||
+            last_in(&[atom!("thead"), atom!("tbody"), atom!("tfoot")]),
+        atom!("thead") => followed_by(&[atom!("tbody"), atom!("tfoot")]),
+        atom!("tbody") => followed_by(&[atom!("tbody"), atom!("tfoot")]) ||
+            last_in(&[atom!("table")]),
+        atom!("tfoot") => last_in(&[atom!("table")]),
+        atom!("p") => followed_by(&[
+            atom!("address"), atom!("article"), atom!("aside"), atom!("blockquote"),
+            atom!("details"), atom!("div"), atom!("dl"), atom!("fieldset"), atom!("figcaption"),
+            atom!("figure"), atom!("footer"), atom!("form"), atom!("h1"), atom!("h2"),
+            atom!("h3"), atom!("h4"), atom!("h5"), atom!("h6"), atom!("header"),
+            atom!("hgroup"), atom!("hr"), atom!("main"), atom!("menu"), atom!("nav"),
+            atom!("ol"), atom!("p"), atom!("pre"), atom!("section"), atom!("ul"),
+        ]) || (is_last && parent_name.as_ref().map_or(false, |name| match *name {
+            atom!("a") | atom!("audio") | atom!("del") | atom!("ins") | atom!("map")
+            | atom!("noscript") | atom!("video") => false,
+            _ => true,
+        })),
+        _ => false,
+    }
+}
+
+fn template_contents(node: &NodeRef) -> Option<NodeRef> {
+    node.as_element().and_then(|element| element.template_contents.clone())
+}
+
+/// Whether nothing would be serialized between the start and end tags of this element.
+fn has_no_children(node: &NodeRef) -> bool {
+    node.first_child().is_none() &&
+    template_contents(node).map_or(true, |contents| contents.first_child().is_none())
+}
+
+fn is_whitespace(text: &str) -> bool {
+    text.trim_matches(SELECTOR_WHITESPACE).is_empty()
+}
+
+
+/// Write the HTML serialization of this node and its descendants.
+///
+/// This also provides `to_string()`.
+impl fmt::Display for NodeRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.serialize(&mut FormatterWriter(f)).map_err(|_| fmt::Error)
+    }
+}
+
+/// Adapts a `fmt::Formatter` to `io::Write`.
+///
+/// The serializer only writes complete UTF-8 sequences in each call.
+struct FormatterWriter<'a, 'b: 'a>(&'a mut fmt::Formatter<'b>);
+
+impl<'a, 'b> Write for FormatterWriter<'a, 'b> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let s = try!(str::from_utf8(buf).map_err(|error| {
+            io::Error::new(io::ErrorKind::InvalidData, error)
+        }));
+        try!(self.0.write_str(s).map_err(|_| io::Error::new(io::ErrorKind::Other, "fmt error")));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
     }
 }
 
 impl NodeRef {
     /// Serialize this node and its descendants in HTML syntax to the given stream.
+    ///
+    /// Markup is written as it is generated, without building an intermediate string.
+    /// Errors from the stream are returned rather than causing a panic.
     #[inline]
     pub fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
-        serialize(writer, self, SerializeOpts {
-            traversal_scope: IncludeNode,
-            ..Default::default()
-        })
+        self.serialize_with_options(writer, &SerializeOpts::default())
+    }
+
+    /// Serialize this node and its descendants in HTML syntax to the given stream,
+    /// with the given options.
+    #[inline]
+    pub fn serialize_with_options<W: Write>(&self, writer: &mut W, opts: &SerializeOpts)
+                                            -> Result<()> {
+        HtmlSerializer::new(writer, opts).serialize(self, IncludeNode)
+    }
+
+    /// Serialize this node and its descendants in HTML syntax to a new string,
+    /// with the given options.
+    #[inline]
+    pub fn to_string_with_options(&self, opts: &SerializeOpts) -> String {
+        let mut u8_vec = Vec::new();
+        self.serialize_with_options(&mut u8_vec, opts).unwrap();
+        String::from_utf8(u8_vec).unwrap()
+    }
+
+    /// Serialize the whole document that this node is part of in HTML syntax to a new string.
+    ///
+    /// This starts from the root of the tree (normally the document node),
+    /// so that the result includes the doctype, if any,
+    /// with its public and system identifiers.
+    #[inline]
+    pub fn serialize_document(&self) -> String {
+        self.root().to_string()
+    }
+
+    /// Serialize this node’s children (but not the node itself) in HTML syntax to a new string.
+    #[inline]
+    pub fn inner_html(&self) -> String {
+        let mut u8_vec = Vec::new();
+        let opts = SerializeOpts::default();
+        HtmlSerializer::new(&mut u8_vec, &opts).serialize(self, ChildrenOnly).unwrap();
+        String::from_utf8(u8_vec).unwrap()
+    }
+
+    /// Serialize the children of this document’s `<body>` element in HTML syntax
+    /// to a new string, for embedding into another document.
+    ///
+    /// The doctype and the `<html>`, `<head>`, and `<body>` tags are not included,
+    /// and neither is any content of `<head>`, such as `<title>` or `<style>`.
+    /// If there is no `<body>` element (for example with a `<frameset>`),
+    /// the result is empty.
+    ///
+    /// This is only a change of scope: combine it with `sanitize`
+    /// if the document is not trusted.
+    pub fn body_html(&self) -> String {
+        let body = self.children()
+            .filter(|node| node.as_element().map_or(false, |e| e.name == qualname!(html, "html")))
+            .flat_map(|html| html.children())
+            .find(|node| node.as_element().map_or(false, |e| e.name == qualname!(html, "body")));
+        match body {
+            Some(body) => body.inner_html(),
+            None => String::new()
+        }
     }
 
     /// Serialize this node and its descendants in HTML syntax to a new file at the given path.
@@ -1,9 +1,16 @@
 use html5ever::tree_builder::QuirksMode;
+use std::iter;
 use std::path::Path;
+use string_cache::{Atom, QualName};
 
 use tempdir::TempDir;
 
-use parser::parse_html;
+use parser::{parse_html, parse_html_with_source_positions, parse_html_with_errors, ParseOpts};
+use parser::parse_html_with_options;
+use parser::{parse_html_bytes, parse_fragment_in};
+use names::{html_name, attr_name};
+use tree::NodeRef;
+use serializer::SerializeOpts;
 use traits::*;
 
 #[test]
@@ -121,3 +128,2197 @@ fn from_bytes() {
         .one(&b"<html><head><title>hey</title></head><body>lol</body></html>"[..]);
     assert_eq!(document.select("title").unwrap().next().unwrap().text_contents(), "hey");
 }
+
+#[test]
+fn clone_subtree() {
+    let html = r"<div id=original><!-- a --><p class=foo>Foo<b>Bar</b>Baz</p><!-- b --></div>";
+    let document = parse_html().one(html);
+    let div = document.select("div").unwrap().next().unwrap();
+    let original = div.as_node();
+    let copy = original.clone_subtree();
+
+    assert!(copy != *original);
+    assert!(copy.parent().is_none());
+    assert!(copy.previous_sibling().is_none());
+    assert!(copy.next_sibling().is_none());
+    assert_eq!(copy.to_string(), original.to_string());
+    assert_eq!(copy.to_string(),
+               "<div id=\"original\"><!-- a --><p class=\"foo\">Foo<b>Bar</b>Baz</p><!-- b --></div>");
+
+    copy.as_element().unwrap().attributes.borrow_mut().insert("id", "copy".to_owned());
+    *copy.descendants().text_nodes().next().unwrap().borrow_mut() = "Changed".to_owned();
+    *copy.first_child().unwrap().as_comment().unwrap().borrow_mut() = " c ".to_owned();
+    assert_eq!(original.to_string(),
+               "<div id=\"original\"><!-- a --><p class=\"foo\">Foo<b>Bar</b>Baz</p><!-- b --></div>");
+    assert_eq!(copy.to_string(),
+               "<div id=\"copy\"><!-- c --><p class=\"foo\">Changed<b>Bar</b>Baz</p><!-- b --></div>");
+}
+
+#[test]
+fn text_contents() {
+    let html = r"<div><p>One <!-- not text --><b>Two <i>Three</i></b> Four</p><p></p></div>";
+    let document = parse_html().one(html);
+    let div = document.select("div").unwrap().next().unwrap();
+    assert_eq!(div.as_node().text_contents(), "One Two Three Four");
+    let empty = div.as_node().last_child().unwrap();
+    assert_eq!(empty.text_contents(), "");
+    let text = div.as_node().descendants().text_nodes().next().unwrap();
+    assert_eq!(text.as_node().text_contents(), "One ");
+}
+
+#[test]
+fn class_list() {
+    let html = r#"<p class="  btn-primary btn   btn
+                   large">"#;
+    let document = parse_html().one(html);
+    let p = document.select("p").unwrap().next().unwrap();
+    assert!(p.has_class("btn"));
+    assert!(p.has_class("large"));
+    assert!(!p.has_class("btn-"));
+    assert!(!p.has_class(""));
+
+    p.add_class("btn");
+    assert_eq!(p.attributes.borrow().get("class"), Some("btn-primary btn large"));
+    p.remove_class("btn");
+    assert_eq!(p.attributes.borrow().get("class"), Some("btn-primary large"));
+    assert!(!p.has_class("btn"));
+    assert!(p.has_class("btn-primary"));
+    p.add_class("small");
+    assert_eq!(p.attributes.borrow().get("class"), Some("btn-primary large small"));
+    p.remove_class("btn-primary");
+    p.remove_class("large");
+    p.remove_class("small");
+    assert_eq!(p.attributes.borrow().get("class"), Some(""));
+
+    let div = parse_html().one("<div>").select("div").unwrap().next().unwrap();
+    div.remove_class("foo");
+    assert_eq!(div.attributes.borrow().get("class"), None);
+    div.add_class("foo");
+    assert_eq!(div.attributes.borrow().get("class"), Some("foo"));
+}
+
+#[test]
+fn append_children() {
+    let document = parse_html().one("<div><p>1</p></div><span>2</span><span>3</span>");
+    let div = document.select("div").unwrap().next().unwrap();
+    let spans = document.select("span").unwrap().map(|span| span.as_node().clone());
+    div.as_node().append_children(spans.collect::<Vec<_>>());
+    assert_eq!(div.as_node().to_string(), "<div><p>1</p><span>2</span><span>3</span></div>");
+    assert_eq!(div.as_node().last_child().unwrap().text_contents(), "3");
+    assert_eq!(document.select("body").unwrap().next().unwrap().as_node().children().count(), 1);
+
+    let empty = NodeRef::new_document();
+    empty.append_children(Vec::new());
+    assert!(empty.first_child().is_none());
+    assert!(empty.last_child().is_none());
+}
+
+#[test]
+fn replace_with() {
+    let document = parse_html().one("<p><a>1</a><b>2</b><i>3</i></p><em>new</em>");
+    let p = document.select("p").unwrap().next().unwrap();
+    let p = p.as_node();
+    let replacement = || document.select("em").unwrap().next().unwrap().as_node().clone();
+
+    let b = document.select("b").unwrap().next().unwrap().as_node().clone();
+    b.replace_with(replacement());
+    assert_eq!(p.to_string(), "<p><a>1</a><em>new</em><i>3</i></p>");
+    assert!(b.parent().is_none() && b.previous_sibling().is_none() && b.next_sibling().is_none());
+
+    let a = p.first_child().unwrap();
+    a.replace_with(b.clone());
+    assert_eq!(p.to_string(), "<p><b>2</b><em>new</em><i>3</i></p>");
+    assert!(p.first_child().unwrap() == b);
+    assert!(b.previous_sibling().is_none());
+
+    let i = p.last_child().unwrap();
+    i.replace_with(a.clone());
+    assert_eq!(p.to_string(), "<p><b>2</b><em>new</em><a>1</a></p>");
+    assert!(p.last_child().unwrap() == a);
+    assert!(a.next_sibling().is_none());
+    assert!(a.previous_sibling().unwrap().next_sibling().unwrap() == a);
+
+    // Without a parent, the replacement stays where it was.
+    i.replace_with(a.clone());
+    assert!(a.parent().unwrap() == *p);
+}
+
+#[test]
+fn unwrap() {
+    let document = parse_html().one("<p>1<span>2<b>3</b>4</span>5</p><div><i>6</i></div>");
+    let p = document.select("p").unwrap().next().unwrap();
+    let span = document.select("span").unwrap().next().unwrap();
+    span.as_node().unwrap();
+    assert_eq!(p.as_node().to_string(), "<p>12<b>3</b>45</p>");
+    assert!(span.as_node().parent().is_none());
+    assert!(span.as_node().first_child().is_none());
+    let b = document.select("b").unwrap().next().unwrap();
+    assert!(b.as_node().parent().unwrap() == *p.as_node());
+    assert_eq!(p.as_node().children().count(), 5);
+    assert_eq!(p.as_node().children().rev().count(), 5);
+
+    let div = document.select("div").unwrap().next().unwrap();
+    let i = document.select("i").unwrap().next().unwrap();
+    i.as_node().unwrap();
+    assert_eq!(div.as_node().to_string(), "<div>6</div>");
+    assert!(div.as_node().first_child() == div.as_node().last_child());
+
+    div.as_node().detach();
+    let text = div.as_node().first_child().unwrap();
+    div.as_node().unwrap();
+    assert!(div.as_node().first_child().is_none());
+    assert!(text.parent().is_none());
+    assert_eq!(text.text_contents(), "6");
+}
+
+#[test]
+fn wrap() {
+    let document = parse_html().one("<p>1<span>2</span>3</p><b></b>");
+    let p = document.select("p").unwrap().next().unwrap();
+    let span = document.select("span").unwrap().next().unwrap();
+    let b = document.select("b").unwrap().next().unwrap();
+    span.as_node().wrap(b.as_node().clone());
+    assert_eq!(p.as_node().to_string(), "<p>1<b><span>2</span></b>3</p>");
+    assert!(b.as_node().parent().unwrap() == *p.as_node());
+    assert_eq!(b.as_node().previous_sibling().unwrap().text_contents(), "1");
+    assert_eq!(b.as_node().next_sibling().unwrap().text_contents(), "3");
+    assert!(p.as_node().first_child().unwrap().next_sibling().unwrap() == *b.as_node());
+    assert!(p.as_node().last_child().unwrap().previous_sibling().unwrap() == *b.as_node());
+
+    let last = p.as_node().last_child().unwrap();
+    let i = NodeRef::new_element(qualname!(html, "i"), None);
+    last.wrap(i.clone());
+    assert_eq!(p.as_node().to_string(), "<p>1<b><span>2</span></b><i>3</i></p>");
+    assert!(p.as_node().last_child().unwrap() == i);
+}
+
+#[test]
+#[should_panic]
+fn wrap_in_text() {
+    let node = NodeRef::new_element(qualname!(html, "i"), None);
+    node.wrap(NodeRef::new_text("not an element"))
+}
+
+#[test]
+#[should_panic]
+fn replace_with_ancestor() {
+    let document = parse_html().one("<div><p><i>text</i></p></div>");
+    let div = document.select_first("div").unwrap().unwrap().as_node().clone();
+    let i = document.select_first("i").unwrap().unwrap().as_node().clone();
+    i.replace_with(div)
+}
+
+#[test]
+#[should_panic]
+fn wrap_in_ancestor() {
+    let document = parse_html().one("<div><p><i>text</i></p></div>");
+    let div = document.select_first("div").unwrap().unwrap().as_node().clone();
+    let i = document.select_first("i").unwrap().unwrap().as_node().clone();
+    i.wrap(div)
+}
+
+#[test]
+#[should_panic]
+fn wrap_in_self() {
+    let node = NodeRef::new_element(qualname!(html, "i"), None);
+    node.wrap(node.clone())
+}
+
+#[test]
+fn element_children() {
+    let document = parse_html().one("<ul> <li>1</li> <!-- 2 --> <li>3</li> text </ul>");
+    let ul = document.select("ul").unwrap().next().unwrap();
+    assert_eq!(ul.as_node().children().count(), 7);
+    let items = ul.as_node().children().elements().collect::<Vec<_>>();
+    assert_eq!(items.len(), 2);
+    assert_eq!(&*items[0].name.local, "li");
+    assert_eq!(items[1].text_contents(), "3");
+    let last = ul.as_node().children().elements().next_back().unwrap();
+    assert!(*last.as_node() == *items[1].as_node());
+}
+
+#[test]
+fn ancestors() {
+    let document = parse_html().one("<div><p>Foo <b>bar</b></p></div>");
+    let text = document.descendants().text_nodes().last().unwrap();
+    let breadcrumbs = text.as_node().ancestors().elements()
+        .map(|element| element.name.local.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(breadcrumbs, ["b", "p", "div", "body", "html"]);
+    assert!(text.as_node().ancestors().last().unwrap() == document);
+    assert_eq!(document.ancestors().count(), 0);
+    assert_eq!(document.inclusive_ancestors().count(), 1);
+}
+
+#[test]
+fn traverse() {
+    use iter::NodeEdge;
+
+    let document = parse_html().one("<p>a<br>b</p>");
+    let p = document.select("p").unwrap().next().unwrap();
+    let describe = |node: &NodeRef| match node.as_element() {
+        Some(element) => element.name.local.to_string(),
+        None => node.text_contents(),
+    };
+    let edges = p.as_node().traverse_inclusive().map(|edge| match edge {
+        NodeEdge::Start(node) => format!("<{}>", describe(&node)),
+        NodeEdge::End(node) => format!("</{}>", describe(&node)),
+    }).collect::<Vec<_>>();
+    assert_eq!(edges, ["<p>", "<a>", "</a>", "<br>", "</br>", "<b>", "</b>", "</p>"]);
+
+    let backward = p.as_node().traverse().rev().collect::<Vec<_>>();
+    assert_eq!(backward.len(), 6);
+    assert!(matches!(backward[0], NodeEdge::End(ref node) if node.as_text().is_some()));
+
+    let br = p.as_node().children().nth(1).unwrap();
+    assert_eq!(br.traverse().count(), 0);
+    assert_eq!(br.traverse_inclusive().collect::<Vec<_>>(),
+               [NodeEdge::Start(br.clone()), NodeEdge::End(br.clone())]);
+}
+
+#[test]
+fn deep_descendants() {
+    let depth = 10_000;
+    let root = NodeRef::new_element(qualname!(html, "div"), None);
+    let mut node = root.clone();
+    for _ in 0..depth {
+        let child = NodeRef::new_element(qualname!(html, "div"), None);
+        node.append(child.clone());
+        node = child;
+    }
+    node.append(NodeRef::new_text("deep"));
+
+    assert_eq!(root.descendants().count(), depth + 1);
+    assert_eq!(root.descendants().rev().count(), depth + 1);
+    assert_eq!(root.inclusive_descendants().count(), depth + 2);
+    assert!(root.descendants().last().unwrap().as_text().is_some());
+    assert_eq!(root.text_contents(), "deep");
+}
+
+#[test]
+fn select_selector_kinds() {
+    let html = r#"
+<div id=main>
+    <p class="a b">1</p>
+    <section><p lang=en>2</p></section>
+</div>
+<p>3</p>"#;
+    let document = parse_html().one(html);
+    let texts = |selectors| {
+        document.select(selectors).unwrap().map(|p| p.text_contents()).collect::<Vec<_>>()
+    };
+    assert_eq!(texts("p"), ["1", "2", "3"]);
+    assert_eq!(texts(".b"), ["1"]);
+    assert_eq!(texts("#main p"), ["1", "2"]);
+    assert_eq!(texts("#main > p"), ["1"]);
+    assert_eq!(texts("[lang]"), ["2"]);
+    assert_eq!(texts("p.a, section p"), ["1", "2"]);
+    assert!(document.select("p[").is_err());
+    assert!(document.select("::before").is_err());
+}
+
+#[test]
+fn select_first() {
+    let document = parse_html().one("<p class=foo>1</p><p class=foo>2</p>");
+    assert_eq!(document.select_first(".foo").unwrap().unwrap().text_contents(), "1");
+    assert!(document.select_first(".bar").unwrap().is_none());
+    assert!(document.select_first("..").is_err());
+}
+
+#[test]
+fn matches() {
+    let document = parse_html().one("<div class=a><p>1</p><p class=b>2</p></div>");
+    let p = document.select_first(".b").unwrap().unwrap();
+    let p = p.as_node();
+    assert_eq!(p.matches("p"), Ok(true));
+    assert_eq!(p.matches("div.a > p + p.b"), Ok(true));
+    assert_eq!(p.matches("p:first-child"), Ok(false));
+    assert_eq!(p.matches("section p"), Ok(false));
+    assert_eq!(p.first_child().unwrap().matches("*"), Ok(false));
+    assert!(p.matches("p >").is_err());
+}
+
+#[test]
+fn closest() {
+    let html = r"<table class=card><tr><td><div class=inner><b>Foo</b></div></td></tr></table>";
+    let document = parse_html().one(html);
+    let text = document.descendants().text_nodes().next().unwrap();
+    let text = text.as_node();
+    assert_eq!(text.closest(".card").unwrap().unwrap().name.local, atom!("table"));
+    assert_eq!(text.closest("div").unwrap().unwrap().name.local, atom!("div"));
+    assert_eq!(text.closest("b").unwrap().unwrap().name.local, atom!("b"));
+    let b = text.parent().unwrap();
+    assert!(*b.closest("b").unwrap().unwrap().as_node() == b);
+    assert!(text.closest("section").unwrap().is_none());
+    assert!(text.closest("[").is_err());
+}
+
+#[test]
+fn serialize_round_trip() {
+    let html = "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"></head>\
+                <body><!-- comment --><p title=\"a &amp; &quot;b&quot;\">1 &lt; 2<br>\
+                <img src=\"x.png\"></p></body></html>";
+    let document = parse_html().one(html);
+    assert_eq!(document.to_string(), html);
+    assert_eq!(parse_html().one(document.to_string()).to_string(), html);
+    let p = document.select_first("p").unwrap().unwrap();
+    assert_eq!(p.as_node().to_string(),
+               "<p title=\"a &amp; &quot;b&quot;\">1 &lt; 2<br><img src=\"x.png\"></p>");
+}
+
+#[test]
+fn serialize_to_failing_writer() {
+    use std::io::{self, Write};
+
+    struct BrokenPipe;
+    impl Write for BrokenPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    let document = parse_html().one("<p>Foo</p>");
+    let error = document.serialize(&mut BrokenPipe).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+
+    let mut bytes = Vec::new();
+    document.serialize(&mut bytes).unwrap();
+    assert_eq!(bytes, document.to_string().into_bytes());
+}
+
+#[test]
+fn inner_html() {
+    let document = parse_html().one("<div class=foo><p>Foo</p> <!-- bar --></div><table></table>");
+    let div = document.select_first("div").unwrap().unwrap();
+    let div = div.as_node();
+    assert_eq!(div.inner_html(), "<p>Foo</p> <!-- bar -->");
+
+    div.set_inner_html("<b>Bar</b> <i>Baz");
+    assert_eq!(div.inner_html(), "<b>Bar</b> <i>Baz</i>");
+    assert_eq!(div.to_string(), "<div class=\"foo\"><b>Bar</b> <i>Baz</i></div>");
+    let html = div.inner_html();
+    div.set_inner_html(&html);
+    assert_eq!(div.inner_html(), html);
+    assert!(div.first_child().unwrap().parent().unwrap() == *div);
+
+    let table = document.select_first("table").unwrap().unwrap();
+    table.as_node().set_inner_html("<tr><td>1</td></tr>");
+    assert_eq!(table.as_node().inner_html(), "<tbody><tr><td>1</td></tr></tbody>");
+}
+
+#[test]
+fn append_prepend_html() {
+    let document = parse_html().one("<ul><li>2</li></ul><table><tbody><tr><td>2</td></tr></tbody></table>");
+    let ul = document.select_first("ul").unwrap().unwrap();
+    ul.as_node().append_html("<li>3</li> <li>4");
+    ul.as_node().prepend_html("<li>0</li><li>1</li>");
+    assert_eq!(ul.as_node().to_string(),
+               "<ul><li>0</li><li>1</li><li>2</li><li>3</li> <li>4</li></ul>");
+
+    let tbody = document.select_first("tbody").unwrap().unwrap();
+    tbody.as_node().append_html("<tr><td>3</td></tr>");
+    tbody.as_node().prepend_html("<tr><td>1</td></tr>");
+    assert_eq!(tbody.as_node().to_string(),
+               "<tbody><tr><td>1</td></tr><tr><td>2</td></tr><tr><td>3</td></tr></tbody>");
+
+    let empty = NodeRef::new_element(qualname!(html, "div"), None);
+    empty.prepend_html("<p>1</p>");
+    empty.prepend_html("");
+    assert_eq!(empty.to_string(), "<div><p>1</p></div>");
+}
+
+#[test]
+fn parse_from_read() {
+    let mut input: &[u8] = b"<title>Foo</title><p><b>Unclosed <i>tags</p><svg><rect/></svg>";
+    let document = parse_html().from_utf8().read_from(&mut input).unwrap();
+    assert_eq!(document.as_document().unwrap().quirks_mode(), QuirksMode::Quirks);
+    assert_eq!(document.select_first("p").unwrap().unwrap().as_node().to_string(),
+               "<p><b>Unclosed <i>tags</i></b></p>");
+    assert_eq!(document.select_first("p").unwrap().unwrap().name.ns, ns!(html));
+    assert_eq!(document.select_first("rect").unwrap().unwrap().name.ns, ns!(svg));
+}
+
+#[test]
+fn parse_from_read_incrementally() {
+    use std::io::{self, Read};
+
+    /// Returns at most one byte per call, then optionally an error.
+    struct Trickle<'a>(&'a [u8], bool);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&byte, rest)) => {
+                    buf[0] = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                None if self.1 => Err(io::Error::new(io::ErrorKind::Other, "oops")),
+                None => Ok(0)
+            }
+        }
+    }
+
+    let html = "<p>Café ☃ 𝄞</p>";
+    let document = parse_html().from_utf8().read_from(&mut Trickle(html.as_bytes(), false)).unwrap();
+    assert_eq!(document.select_first("p").unwrap().unwrap().text_contents(), "Café ☃ 𝄞");
+
+    let error = parse_html().from_utf8().read_from(&mut Trickle(html.as_bytes(), true)).unwrap_err();
+    assert_eq!(error.to_string(), "oops");
+}
+
+#[test]
+fn parse_with_options() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use parser::{parse_html_with_options, ParseOpts};
+
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let errors2 = errors.clone();
+    let opts = ParseOpts {
+        on_parse_error: Some(Box::new(move |error| errors2.borrow_mut().push(error))),
+        ..ParseOpts::default()
+    };
+    parse_html_with_options(opts).one("<p></b>");
+    assert!(!errors.borrow().is_empty());
+}
+
+#[test]
+fn parse_fragment_with_context() {
+    use parser::parse_fragment;
+
+    let html = "<tr><td>Foo</td></tr>";
+    let document = parse_fragment(qualname!(html, "tbody"), Vec::new()).one(html);
+    let root = document.first_child().unwrap();
+    assert_eq!(root.as_element().unwrap().name, qualname!(html, "html"));
+    assert_eq!(root.inner_html(), html);
+
+    let document = parse_fragment(qualname!(html, "div"), Vec::new()).one(html);
+    assert_eq!(document.first_child().unwrap().inner_html(), "Foo");
+
+    let document = parse_fragment(qualname!(html, "table"), Vec::new()).one(html);
+    assert_eq!(document.first_child().unwrap().inner_html(), "<tbody><tr><td>Foo</td></tr></tbody>");
+}
+
+#[test]
+fn attribute_accessors() {
+    let document = parse_html().one(r#"<a href="/foo" xml:lang="en">"#);
+    let a = document.select_first("a").unwrap().unwrap();
+    assert_eq!(a.get_attribute("href"), Some("/foo".to_owned()));
+    assert_eq!(a.get_attribute("title"), None);
+    a.set_attribute("title", "Foo");
+    a.set_attribute("href", "/bar");
+    assert_eq!(a.get_attribute("title"), Some("Foo".to_owned()));
+    assert_eq!(a.get_attribute("href"), Some("/bar".to_owned()));
+    a.remove_attribute("href");
+    a.remove_attribute("href");
+    assert_eq!(a.get_attribute("href"), None);
+    assert_eq!(a.attributes.borrow().list.len(), 2);
+}
+
+#[test]
+fn attribute_order() {
+    let html = r#"<p z="1" a="2" m="3" a="ignored duplicate"></p>"#;
+    let document = parse_html().one(html);
+    let p = document.select_first("p").unwrap().unwrap();
+    assert_eq!(p.as_node().to_string(), r#"<p z="1" a="2" m="3"></p>"#);
+    p.set_attribute("a", "4");
+    p.set_attribute("b", "5");
+    p.remove_attribute("z");
+    assert_eq!(p.as_node().to_string(), r#"<p a="4" m="3" b="5"></p>"#);
+
+    let other = NodeRef::new_element(qualname!(html, "p"), vec![
+        (QualName::new(ns!(), Atom::from("b")), "5".to_owned()),
+        (QualName::new(ns!(), Atom::from("m")), "3".to_owned()),
+        (QualName::new(ns!(), Atom::from("a")), "4".to_owned()),
+    ]);
+    assert_eq!(*p.attributes.borrow(), *other.as_element().unwrap().attributes.borrow());
+}
+
+#[test]
+fn normalize() {
+    let document = parse_html().one("<p>1<!-- c -->2<b>3</b></p>");
+    let p = document.select_first("p").unwrap().unwrap();
+    let p = p.as_node();
+    let b = p.last_child().unwrap();
+    b.insert_before(NodeRef::new_text("a"));
+    b.insert_before(NodeRef::new_text(""));
+    b.insert_before(NodeRef::new_text("b"));
+    b.append(NodeRef::new_text(""));
+    p.append(NodeRef::new_text(""));
+    p.append(NodeRef::new_text(""));
+    assert_eq!(p.children().count(), 9);
+
+    p.normalize();
+    let children = p.children().map(|child| child.to_string()).collect::<Vec<_>>();
+    assert_eq!(children, ["1", "<!-- c -->", "2ab", "<b>3</b>"]);
+    assert_eq!(b.children().count(), 1);
+    assert!(p.last_child().unwrap() == b);
+    assert!(b.previous_sibling().unwrap().next_sibling().unwrap() == b);
+}
+
+#[test]
+fn split_text() {
+    let document = parse_html().one("<p>Hé llo</p>");
+    let p = document.select_first("p").unwrap().unwrap();
+    let text = p.as_node().first_child().unwrap();
+    let suffix = text.split_text(3);
+    assert_eq!(text.text_contents(), "Hé");
+    assert_eq!(suffix.text_contents(), " llo");
+    assert!(text.next_sibling().unwrap() == suffix);
+    assert!(p.as_node().last_child().unwrap() == suffix);
+
+    suffix.insert_before(NodeRef::new_element(qualname!(html, "br"), None));
+    assert_eq!(p.as_node().to_string(), "<p>Hé<br> llo</p>");
+
+    let empty_suffix = suffix.split_text(4);
+    assert_eq!(empty_suffix.text_contents(), "");
+    let rest = suffix.split_text(0);
+    assert_eq!(suffix.text_contents(), "");
+    assert_eq!(rest.text_contents(), " llo");
+    assert_eq!(p.as_node().children().count(), 5);
+}
+
+#[test]
+#[should_panic]
+fn split_text_inside_char() {
+    NodeRef::new_text("é").split_text(1);
+}
+
+#[test]
+fn attribute_ignore_case() {
+    let document = parse_html().one(r##"<svg><a xlink:href="#foo" data-Id="1"></a></svg>"##);
+    let a = document.select_first("a").unwrap().unwrap();
+    a.set_attribute("TITLE", "Foo");
+    assert_eq!(a.get_attribute_ignore_case("DataId"), None);
+    assert_eq!(a.get_attribute_ignore_case("DATA-ID"), Some("1".to_owned()));
+    assert_eq!(a.get_attribute_ignore_case("title"), Some("Foo".to_owned()));
+    assert!(a.has_attribute_ignore_case("Title"));
+    assert!(!a.has_attribute_ignore_case("href"));
+}
+
+#[test]
+fn dataset() {
+    let html = r#"<div data-user-id="1" data-x="2" data-foo-Bar="3" data-a--b="4" data="5" id="6">"#;
+    let document = parse_html().one(html);
+    let div = document.select_first("div").unwrap().unwrap();
+
+    let dataset = div.dataset();
+    assert_eq!(dataset.len(), 4);
+    assert_eq!(dataset["user-id"], "1");
+    assert_eq!(dataset["x"], "2");
+    assert_eq!(dataset["foo-bar"], "3");
+
+    let dataset = div.dataset_camel_case();
+    assert_eq!(dataset.len(), 4);
+    assert_eq!(dataset["userId"], "1");
+    assert_eq!(dataset["x"], "2");
+    assert_eq!(dataset["fooBar"], "3");
+    assert_eq!(dataset["a-B"], "4");
+}
+
+#[test]
+fn get_element_by_id() {
+    let html = r"<div id=a><p id=b>1</p><p id=b>2</p></div><span id=c>3</span>";
+    let document = parse_html().one(html);
+    assert_eq!(document.get_element_by_id("b").unwrap().text_contents(), "1");
+    assert_eq!(document.get_element_by_id("c").unwrap().text_contents(), "3");
+    assert!(document.get_element_by_id("d").is_none());
+    assert!(document.get_element_by_id("").is_none());
+
+    let div = document.get_element_by_id("a").unwrap();
+    assert_eq!(div.as_node().get_element_by_id("b").unwrap().text_contents(), "1");
+    assert!(div.as_node().get_element_by_id("c").is_none());
+
+    let first = document.get_element_by_id("b").unwrap();
+    first.as_node().detach();
+    document.as_document().unwrap().invalidate_id_cache();
+    assert_eq!(document.get_element_by_id("b").unwrap().text_contents(), "2");
+
+    let copy = document.clone_subtree();
+    assert!(*copy.get_element_by_id("c").unwrap().as_node() !=
+            *document.get_element_by_id("c").unwrap().as_node());
+}
+
+#[test]
+fn text_and_comment_iterators() {
+    let html = "<!DOCTYPE html><!-- 1 --><title>2</title><p>3<!-- 4 --><b>5</b></p>";
+    let document = parse_html().one(html);
+    let texts = document.descendants().text_nodes()
+        .map(|text| text.borrow().clone()).collect::<Vec<_>>();
+    assert_eq!(texts, ["2", "3", "5"]);
+    let comments = document.descendants().comments()
+        .map(|comment| comment.borrow().clone()).collect::<Vec<_>>();
+    assert_eq!(comments, [" 1 ", " 4 "]);
+
+    for text in document.descendants().text_nodes() {
+        let upper = text.borrow().to_uppercase();
+        *text.borrow_mut() = upper + "!";
+    }
+    assert_eq!(document.text_contents(), "2!3!5!");
+}
+
+#[test]
+fn pretty_print() {
+    let html = "<!DOCTYPE html><html><head><title>Test</title></head>\
+                <body><div><p>Some <em>inline</em> text</p>  <!-- c --><hr></div>\
+                <pre>\n  keep\n   this</pre></body></html>";
+    let document = parse_html().one(html);
+    assert_eq!(document.to_string_with_options(&SerializeOpts::default()),
+               document.to_string());
+
+    let opts = SerializeOpts { indent: Some(2), ..SerializeOpts::default() };
+    assert_eq!(document.to_string_with_options(&opts), "\
+<!DOCTYPE html>
+<html>
+  <head>
+    <title>Test</title>
+  </head>
+  <body>
+    <div>
+      <p>Some <em>inline</em> text</p>
+      <!-- c -->
+      <hr>
+    </div>
+    <pre>  keep\n   this</pre>
+  </body>
+</html>");
+
+    let div = document.select_first("div").unwrap().unwrap();
+    assert_eq!(div.as_node().to_string_with_options(&opts), "\
+<div>
+  <p>Some <em>inline</em> text</p>
+  <!-- c -->
+  <hr>
+</div>");
+}
+
+#[test]
+fn deep_eq() {
+    let html = "<p class=a id=b>Foo<!-- bar --><b>Baz</b></p><template><i>1</i></template>";
+    let document = parse_html().one(html);
+    let copy = parse_html().one("<p id=b class=a>Foo<!-- bar --><b>Baz</b></p>\
+                                 <template><i>1</i></template>");
+    assert!(document != copy);
+    assert!(document.deep_eq(&copy));
+    assert!(document.deep_eq(&document.clone_subtree()));
+
+    let p = document.select_first("p").unwrap().unwrap();
+    assert!(!p.as_node().deep_eq(&copy));
+    assert!(p.as_node().deep_eq(&copy.select_first("p").unwrap().unwrap().as_node()));
+
+    copy.select_first("b").unwrap().unwrap().as_node().append(NodeRef::new_text("!"));
+    assert!(!document.deep_eq(&copy));
+    copy.select_first("b").unwrap().unwrap().as_node().last_child().unwrap().detach();
+    assert!(document.deep_eq(&copy));
+
+    copy.select_first("p").unwrap().unwrap().attributes.borrow_mut().insert("id", "c".into());
+    assert!(!document.deep_eq(&copy));
+
+    let template = copy.select_first("template").unwrap().unwrap();
+    assert!(!template.as_node().deep_eq(&NodeRef::new_element(qualname!(html, "template"), vec![])));
+}
+
+#[test]
+fn is_same_node_is_equal_node() {
+    let document = parse_html().one("<p class=a>Foo</p><p class=a>Bar</p><p class=b>Foo</p>");
+    let paragraphs = document.select("p").unwrap().map(|p| p.as_node().clone())
+        .collect::<Vec<_>>();
+    assert!(paragraphs[0].is_same_node(&paragraphs[0]));
+    assert!(paragraphs[0].is_same_node(&paragraphs[0].clone()));
+    assert!(!paragraphs[0].is_same_node(&paragraphs[1]));
+
+    assert!(paragraphs[0].is_equal_node(&paragraphs[1]));
+    assert!(!paragraphs[0].deep_eq(&paragraphs[1]));
+    assert!(!paragraphs[0].is_equal_node(&paragraphs[2]));
+    assert!(paragraphs[0].first_child().unwrap().is_equal_node(&paragraphs[2].first_child().unwrap()));
+    assert!(!paragraphs[0].is_equal_node(&paragraphs[0].first_child().unwrap()));
+}
+
+#[test]
+fn remove_children() {
+    let document = parse_html().one("<div><p>Foo<b>Bar</b></p><p>Baz</p></div>");
+    let div = document.select_first("div").unwrap().unwrap();
+    let div = div.as_node();
+    let b = ::rc::Rc::downgrade(&document.select_first("b").unwrap().unwrap().as_node().0);
+    let kept = div.last_child().unwrap();
+    assert!(b.upgrade().is_some());
+
+    div.remove_children();
+    assert!(div.first_child().is_none());
+    assert!(div.last_child().is_none());
+    assert_eq!(div.to_string(), "<div></div>");
+    assert!(b.upgrade().is_none());
+    assert!(kept.parent().is_none());
+    assert_eq!(kept.to_string(), "<p>Baz</p>");
+
+    div.remove_children();
+    assert!(div.first_child().is_none());
+}
+
+#[test]
+fn descendant_count() {
+    let document = parse_html().one("<p>Foo<b>Bar</b><!-- baz --></p><p></p>");
+    // html, head, body, 2 × p, b, 2 × text, comment
+    assert_eq!(document.descendant_count(), 9);
+    assert_eq!(document.element_count(), 6);
+
+    let p = document.select_first("p").unwrap().unwrap();
+    assert_eq!(p.as_node().descendant_count(), 4);
+    assert_eq!(p.as_node().element_count(), 1);
+    let text = p.as_node().first_child().unwrap();
+    assert_eq!(text.descendant_count(), 0);
+    assert_eq!(text.element_count(), 0);
+}
+
+#[test]
+fn css_path() {
+    let html = "<div id=x><p>1</p></div>\
+                <div><span></span><p>2</p><p id=d>3</p><p id=d>4</p><p id=1>5</p></div>";
+    let document = parse_html().one(html);
+    assert_eq!(document.css_path(), "");
+    for p in document.select("p").unwrap() {
+        let path = p.as_node().css_path();
+        let found = document.select(&path).unwrap()
+            .map(|found| found.as_node().clone()).collect::<Vec<_>>();
+        assert!(found == [p.as_node().clone()]);
+    }
+    let paths = document.select("p").unwrap()
+        .map(|p| p.as_node().first_child().unwrap().css_path()).collect::<Vec<_>>();
+    assert_eq!(paths, [
+        "#x > p",
+        "html > body:nth-child(2) > div:nth-child(2) > p:nth-child(2)",
+        "html > body:nth-child(2) > div:nth-child(2) > p:nth-child(3)",
+        "html > body:nth-child(2) > div:nth-child(2) > p:nth-child(4)",
+        "html > body:nth-child(2) > div:nth-child(2) > p:nth-child(5)",
+    ]);
+}
+
+#[test]
+fn replace_text() {
+    let html = "<p title=foo>foo bar <b>foo</b><!-- foo --> baz</p>";
+    let document = parse_html().one(html);
+    assert_eq!(document.replace_text("foo", "qux"), 2);
+    assert_eq!(document.select_first("p").unwrap().unwrap().as_node().to_string(),
+               "<p title=\"foo\">qux bar <b>qux</b><!-- foo --> baz</p>");
+    assert_eq!(document.replace_text("foo", "qux"), 0);
+    assert_eq!(document.replace_text("", "x"), 0);
+
+    let count = document.replace_text_with(|text| text.to_uppercase());
+    assert_eq!(count, 3);
+    assert_eq!(document.text_contents(), "QUX BAR QUX BAZ");
+
+    let text = document.select_first("b").unwrap().unwrap().as_node().first_child().unwrap();
+    assert_eq!(text.replace_text("QUX", "quux"), 1);
+    assert_eq!(document.text_contents(), "QUX BAR quux BAZ");
+}
+
+#[test]
+fn serialize_escaping_options() {
+    let html = "<p title=\"l'été\">Café&nbsp;&amp; 💩<!-- é --><script>'é'</script></p>";
+    let document = parse_html().one(html);
+    let p = document.select_first("p").unwrap().unwrap();
+    let p = p.as_node();
+    assert_eq!(p.to_string(), html);
+
+    let opts = SerializeOpts { escape_non_ascii: true, ..SerializeOpts::default() };
+    assert_eq!(p.to_string_with_options(&opts),
+               "<p title=\"l'&#xE9;t&#xE9;\">Caf&#xE9;&nbsp;&amp; &#x1F4A9;\
+                <!-- é --><script>'é'</script></p>");
+
+    let opts = SerializeOpts { escape_apostrophes: true, ..SerializeOpts::default() };
+    assert_eq!(p.to_string_with_options(&opts),
+               "<p title=\"l&#39;été\">Café&nbsp;&amp; 💩<!-- é --><script>'é'</script></p>");
+}
+
+#[test]
+fn serialize_raw_text() {
+    let html = "<html><head><style>a > b { content: \"&amp;\" }</style><title>a < b</title></head>\
+                <body><script>if (a < b && c > d) { x = '&lt;' }</script>\
+                <textarea>a < b && c</textarea></body></html>";
+    let document = parse_html().one(html);
+    let script = document.select_first("script").unwrap().unwrap();
+    assert_eq!(script.text_contents(), "if (a < b && c > d) { x = '&lt;' }");
+    let serialized = document.to_string();
+    assert_eq!(serialized,
+               "<html><head><style>a > b { content: \"&amp;\" }</style><title>a &lt; b</title></head>\
+                <body><script>if (a < b && c > d) { x = '&lt;' }</script>\
+                <textarea>a &lt; b &amp;&amp; c</textarea></body></html>");
+    assert!(parse_html().one(serialized).deep_eq(&document));
+}
+
+#[test]
+fn serialize_omit_optional_end_tags() {
+    let html = "<!DOCTYPE html><html><head><title>T</title></head><body>\
+                <ul><li>1</li><li>2 <span>x</span></li></ul>\
+                <dl><dt>a</dt><dd>b</dd><dt>c</dt><dd>d</dd></dl>\
+                <select><optgroup><option>1</option><option>2</option></optgroup></select>\
+                <table><thead><tr><th>h</th></tr></thead>\
+                <tbody><tr><td>1</td><td>2</td></tr><tr><td>3</td></tr></tbody></table>\
+                <p>a</p><p>b</p><div><p>c</p></div><p>d</p><table></table>\
+                <a><p>e</p></a><p>f</p>g<ol><li>3</li> </ol><!-- end --></body></html>";
+    let document = parse_html().one(html);
+    let opts = SerializeOpts { omit_optional_end_tags: true, ..SerializeOpts::default() };
+    let serialized = document.to_string_with_options(&opts);
+    assert_eq!(serialized,
+               "<!DOCTYPE html>\n<html><head><title>T</title><body>\
+                <ul><li>1<li>2 <span>x</span></ul>\
+                <dl><dt>a<dd>b<dt>c<dd>d</dl>\
+                <select><optgroup><option>1<option>2</select>\
+                <table><thead><tr><th>h<tbody><tr><td>1<td>2<tr><td>3</table>\
+                <p>a<p>b<div><p>c</div><p>d</p><table></table>\
+                <a><p>e</p></a><p>f</p>g<ol><li>3</li> </ol><!-- end -->");
+    assert!(parse_html().one(serialized).deep_eq(&document));
+
+    let li = document.select_first("li").unwrap().unwrap();
+    assert_eq!(li.as_node().to_string_with_options(&opts), "<li>1</li>");
+    let pretty = SerializeOpts { indent: Some(2), ..opts.clone() };
+    let ul = document.select_first("ul").unwrap().unwrap();
+    assert_eq!(ul.as_node().to_string_with_options(&pretty),
+               "<ul>\n  <li>1</li>\n  <li>2 <span>x</span></li>\n</ul>");
+}
+
+#[test]
+fn foreign_content_round_trip() {
+    let html = "<p><svg viewBox=\"0 0 1 1\"><rect/><a xlink:href=\"#x\"><foreignObject>\
+                <p>1</p></foreignObject></a></svg><math><mi>x</mi></math></p>";
+    let document = parse_html().one(html);
+    let rect = document.select_first("rect").unwrap().unwrap();
+    assert_eq!(rect.name, qualname!(svg, "rect"));
+    let svg_a = document.select_first("svg|a").unwrap().unwrap();
+    assert_eq!(svg_a.name, qualname!(svg, "a"));
+    assert!(svg_a.attributes.borrow().list.iter().any(|&(ref name, ref value)| {
+        *name == qualname!(xlink, "href") && value == "#x"
+    }));
+    assert_eq!(document.select_first("mi").unwrap().unwrap().name, qualname!(mathml, "mi"));
+    assert_eq!(document.select("svg|*").unwrap().count(), 4);
+    assert_eq!(document.select("math|*").unwrap().count(), 2);
+    assert_eq!(document.select("html|p").unwrap().count(), 2);
+    assert_eq!(document.select("svg|foreignObject > html|p").unwrap().count(), 1);
+    assert_eq!(document.select("[xlink|href]").unwrap().count(), 1);
+    assert!(::Selectors::compile("foo|a").is_err());
+
+    let body = document.select_first("body").unwrap().unwrap();
+    let serialized = body.as_node().inner_html();
+    assert_eq!(serialized,
+               "<p><svg viewBox=\"0 0 1 1\"><rect></rect><a xlink:href=\"#x\"><foreignObject>\
+                <p>1</p></foreignObject></a></svg><math><mi>x</mi></math></p>");
+    assert!(parse_html().one(serialized).deep_eq(&document));
+}
+
+#[test]
+fn serialize_xml() {
+    let html = "<p class=a>1 &lt; 2&nbsp;<br><img src=x.png></p>\
+                <svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\">\
+                <rect width=1></rect><use xlink:href=\"#r\"/></svg><script>a < b</script>";
+    let document = parse_html().one(html);
+    let body = document.select_first("body").unwrap().unwrap();
+    let opts = SerializeOpts { xml: true, ..SerializeOpts::default() };
+    assert_eq!(body.as_node().to_string_with_options(&opts),
+               "<body xmlns=\"http://www.w3.org/1999/xhtml\">\
+                <p class=\"a\">1 &lt; 2\u{A0}<br/><img src=\"x.png\"/></p>\
+                <svg:svg xmlns:svg=\"http://www.w3.org/2000/svg\">\
+                <svg:rect width=\"1\"/>\
+                <svg:use xmlns:xlink=\"http://www.w3.org/1999/xlink\" xlink:href=\"#r\"/>\
+                </svg:svg><script>a &lt; b</script></body>");
+    assert_eq!(body.as_node().to_string(),
+               "<body><p class=\"a\">1 &lt; 2&nbsp;<br><img src=\"x.png\"></p>\
+                <svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\">\
+                <rect width=\"1\"></rect><use xlink:href=\"#r\"></use></svg>\
+                <script>a < b</script></body>");
+
+    let element = NodeRef::new_element(QualName::new(ns!(), Atom::from("item")), vec![]);
+    element.append(NodeRef::new_element(qualname!(svg, "g"), vec![]));
+    assert_eq!(element.to_string_with_options(&opts),
+               "<item><svg:g xmlns:svg=\"http://www.w3.org/2000/svg\"/></item>");
+}
+
+#[test]
+fn source_positions() {
+    let html = "<!DOCTYPE html>\n<title>Foo</title>\n<p>a > b\n  <b\n class=x>bar</b>\n</p>";
+    let document = parse_html_with_source_positions(ParseOpts::default()).one(html);
+    let position = |selector: &str| {
+        document.select_first(selector).unwrap().unwrap().as_node().source_position()
+    };
+    assert_eq!(position("title"), Some((2, 1)));
+    assert_eq!(position("p"), Some((3, 1)));
+    assert_eq!(position("b"), Some((4, 3)));
+    // Implied by `<title>`
+    assert_eq!(position("head"), Some((2, 1)));
+    assert!(document.source_position().is_none());
+    assert!(document.select_first("p").unwrap().unwrap().as_node()
+                    .first_child().unwrap().source_position().is_none());
+
+    let mut input: &[u8] = html.as_bytes();
+    let document = parse_html_with_source_positions(ParseOpts::default())
+        .from_utf8().read_from(&mut input).unwrap();
+    let b = document.select_first("b").unwrap().unwrap();
+    assert_eq!(b.as_node().source_position(), Some((4, 3)));
+    b.as_node().detach();
+    assert!(b.as_node().source_position().is_none());
+
+    // `>` in attribute values, and tags split across inputs.
+    let mut parser = parse_html_with_source_positions(ParseOpts::default());
+    parser.process("<p>x</p>\n <a title='1 > 2'>y</a><".into());
+    parser.process("i>z</i>".into());
+    let document = parser.finish();
+    let position = |selector: &str| {
+        document.select_first(selector).unwrap().unwrap().as_node().source_position()
+    };
+    assert_eq!(position("a"), Some((2, 2)));
+    assert_eq!(position("i"), Some((2, 24)));
+
+    let document = parse_html().one(html);
+    assert!(document.select_first("p").unwrap().unwrap().as_node().source_position().is_none());
+}
+
+#[test]
+fn parse_errors() {
+    let html = "<!DOCTYPE html>\n<p>Foo</i>\n</p>";
+    let (document, errors) = parse_html_with_errors(ParseOpts::default()).one(html);
+    assert_eq!(document.select_first("p").unwrap().unwrap().text_contents(), "Foo\n");
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].position.is_none());
+
+    let (_, errors) = parse_html_with_source_positions(ParseOpts::default())
+        .with_errors().one(html);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].position, Some((2, 10)));
+
+    let (_, errors) = parse_html_with_errors(ParseOpts::default()).one("<!DOCTYPE html><p>Foo</p>");
+    assert!(errors.is_empty());
+}
+
+#[test]
+fn parse_html_bytes_charset() {
+    let text = |document: NodeRef| document.select_first("p").unwrap().unwrap().text_contents();
+
+    // No declaration: windows-1252
+    assert_eq!(text(parse_html_bytes(b"<p>caf\xE9 \x80", None)), "café €");
+    // Transport layer
+    assert_eq!(text(parse_html_bytes(b"<p>caf\xC3\xA9", Some("utf-8"))), "café");
+    assert_eq!(text(parse_html_bytes(b"<p>caf\xC3\xA9", Some("unknown"))), "cafÃ©");
+    // Meta tags
+    assert_eq!(text(parse_html_bytes(b"<meta charset=utf-8><p>caf\xC3\xA9", None)), "café");
+    assert_eq!(text(parse_html_bytes(
+        b"<!-- <meta charset=utf-8> --><title a='>'></title>\
+          <META HTTP-EQUIV=\"Content-Type\" Content=\"text/html; charset='iso-8859-2'\">\
+          <p>\xB1", None)), "ą");
+    // A meta tag without http-equiv is ignored.
+    assert_eq!(text(parse_html_bytes(
+        b"<meta content='text/html; charset=utf-8'><p>\xE9", None)), "é");
+    assert_eq!(text(parse_html_bytes(b"<meta charset=utf-16le><p>\xC3\xA9", None)), "é");
+    // The transport layer overrides meta tags
+    assert_eq!(text(parse_html_bytes(b"<meta charset=utf-8><p>\xE9", Some("latin1"))), "é");
+    // A BOM overrides everything
+    assert_eq!(text(parse_html_bytes(b"\xEF\xBB\xBF<meta charset=latin1><p>\xC3\xA9",
+                                     Some("latin1"))), "é");
+    assert_eq!(text(parse_html_bytes(b"\xFF\xFE<\x00p\x00>\x00\xE9\x00", None)), "é");
+    assert_eq!(text(parse_html_bytes(b"\xFE\xFF\x00<\x00p\x00>\x00\xE9", None)), "é");
+}
+
+#[test]
+fn serialize_document() {
+    let html = "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \
+                \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">\n\
+                <html><head><title>Foo</title></head><body><p>Bar</p></body></html>";
+    let document = parse_html().one(html);
+    let p = document.select_first("p").unwrap().unwrap();
+    assert_eq!(p.as_node().serialize_document(), html);
+    assert_eq!(document.serialize_document(), html);
+
+    let reparsed = parse_html().one(document.serialize_document());
+    let doctype = reparsed.first_child().unwrap();
+    let doctype = doctype.as_doctype().unwrap();
+    assert_eq!(doctype.public_id, "-//W3C//DTD XHTML 1.0 Strict//EN");
+    assert_eq!(doctype.system_id, "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd");
+    assert_eq!(reparsed.as_document().unwrap().quirks_mode(), QuirksMode::NoQuirks);
+
+    let html = "<!DOCTYPE html SYSTEM \"about:legacy-compat\">\n<html><head></head><body></body></html>";
+    assert_eq!(parse_html().one(html).serialize_document(), html);
+}
+
+#[test]
+fn make_orphan() {
+    let document = parse_html().one("<div><p>1<b>2</b></p><p>3</p></div><section></section>");
+    let removed = document.select_first("p").unwrap().unwrap().as_node().make_orphan();
+    assert!(removed.parent().is_none());
+    assert!(removed.previous_sibling().is_none());
+    assert!(removed.next_sibling().is_none());
+    assert_eq!(document.select_first("div").unwrap().unwrap().as_node().to_string(),
+               "<div><p>3</p></div>");
+
+    // Descendants are preserved and come along when the subtree is re-inserted.
+    assert_eq!(removed.to_string(), "<p>1<b>2</b></p>");
+    assert!(removed.first_child().unwrap().parent() == Some(removed.clone()));
+    document.select_first("section").unwrap().unwrap().as_node().append(removed);
+    assert_eq!(document.select_first("section").unwrap().unwrap().as_node().to_string(),
+               "<section><p>1<b>2</b></p></section>");
+}
+
+#[test]
+fn template_contents() {
+    let html = "<html><head></head><body><p>1</p>\
+                <template id=\"t\"><tr><td>2</td></tr><!-- 3 --></template></body></html>";
+    let document = parse_html().one(html);
+    let template = document.select_first("template").unwrap().unwrap();
+    assert!(template.as_node().first_child().is_none());
+    let contents = template.template_contents.as_ref().unwrap();
+    assert_eq!(contents.to_string(), "<tr><td>2</td></tr><!-- 3 -->");
+
+    assert_eq!(document.to_string(), html);
+    assert_eq!(template.as_node().inner_html(), "<tr><td>2</td></tr><!-- 3 -->");
+    assert_eq!(document.text_contents(), "1");
+    assert!(document.select_first("td").unwrap().is_none());
+
+    let mut opts = SerializeOpts::default();
+    opts.xml = true;
+    assert_eq!(template.as_node().to_string_with_options(&opts),
+               "<template xmlns=\"http://www.w3.org/1999/xhtml\" id=\"t\">\
+                <tr><td>2</td></tr><!-- 3 --></template>");
+    let mut opts = SerializeOpts::default();
+    opts.indent = Some(2);
+    assert_eq!(template.as_node().to_string_with_options(&opts),
+               "<template id=\"t\">\n  <tr>\n    <td>2</td>\n  </tr>\n  <!-- 3 -->\n</template>");
+
+    // html5ever’s serializer
+    let mut u8_vec = Vec::new();
+    ::html5ever::serialize::serialize(&mut u8_vec, template.as_node(), Default::default()).unwrap();
+    assert_eq!(String::from_utf8(u8_vec).unwrap(), "<tr><td>2</td></tr><!-- 3 -->");
+}
+
+#[test]
+fn tag_name_namespace() {
+    let document = parse_html().one("<p>Foo<svg><circle/></svg></p>");
+    let p = document.select_first("p").unwrap().unwrap();
+    assert_eq!(p.as_node().tag_name(), Some("p"));
+    assert_eq!(p.as_node().namespace(), Some(&ns!(html)));
+    let circle = document.select_first("circle").unwrap().unwrap();
+    assert_eq!(circle.as_node().tag_name(), Some("circle"));
+    assert_eq!(circle.as_node().namespace(), Some(&ns!(svg)));
+    let text = p.as_node().first_child().unwrap();
+    assert_eq!(text.tag_name(), None);
+    assert_eq!(text.namespace(), None);
+    assert_eq!(document.tag_name(), None);
+}
+
+#[test]
+fn body_html() {
+    let document = parse_html().one("<!DOCTYPE html><title>Foo</title><style>p {}</style>\
+                                     <p class=x>Bar</p><!-- Baz -->");
+    assert_eq!(document.body_html(), "<p class=\"x\">Bar</p><!-- Baz -->");
+    assert_eq!(parse_html().one("").body_html(), "");
+    assert_eq!(parse_html().one("<frameset></frameset>").body_html(), "");
+    // Not a document
+    assert_eq!(document.select_first("p").unwrap().unwrap().as_node().body_html(), "");
+}
+
+#[test]
+fn inner_text() {
+    let html = "<!DOCTYPE html><title>Title</title><style>p { color: red }</style>\n\
+                <h1>  Some\n  <em>title</em> </h1>\n\
+                <div><p>First   paragraph.</p><p>Second<br>line  <b>bold</b>.</p></div>\n\
+                <pre>  keep\n    this</pre><ul><li>a</li> <li>b</li></ul>\
+                <script>var x;</script>";
+    let document = parse_html().one(html);
+    assert_eq!(document.inner_text(),
+               "Some title\nFirst paragraph.\nSecond\nline bold.\n  keep\n    this\na\nb");
+    assert!(document.text_contents().contains("var x;"));
+    let h1 = document.select_first("h1").unwrap().unwrap();
+    assert_eq!(h1.as_node().inner_text(), "Some title");
+    assert_eq!(NodeRef::new_text(" a  b ").inner_text(), "a b");
+}
+
+#[test]
+fn select_with() {
+    use std::thread;
+    fn assert_send<T: Send>(_: &T) {}
+
+    let selectors = ::Selectors::compile("p.foo, b").unwrap();
+    assert_send(&selectors);
+    let selectors_clone = selectors.clone();
+    let count = thread::spawn(move || {
+        let document = parse_html().one("<p class=foo>1</p><p>2<b>3</b></p>");
+        document.select_with(&selectors_clone).count()
+    }).join().unwrap();
+    assert_eq!(count, 2);
+
+    for html in &["<p class=foo>Foo</p><p>Bar</p>", "<b>Foo</b>"] {
+        let document = parse_html().one(*html);
+        let matches = document.select_with(&selectors).collect::<Vec<_>>();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].text_contents(), "Foo");
+        assert_eq!(selectors.filter(document.descendants().elements()).count(), 1);
+    }
+}
+
+#[test]
+fn rename() {
+    let document = parse_html().one("<!DOCTYPE html><p>1<b class=x>2<i>3</i></b>4</p>");
+    let b = document.select_first("b").unwrap().unwrap().as_node().clone();
+    let strong = b.rename(qualname!(html, "strong"));
+    assert_eq!(document.select_first("p").unwrap().unwrap().as_node().to_string(),
+               "<p>1<strong class=\"x\">2<i>3</i></strong>4</p>");
+    assert!(b.parent().is_none());
+    assert!(b.first_child().is_none());
+    assert!(strong.first_child().unwrap().parent() == Some(strong.clone()));
+
+    let detached = NodeRef::new_element(qualname!(html, "b"), None);
+    detached.append(NodeRef::new_text("x"));
+    assert_eq!(detached.rename(qualname!(html, "em")).to_string(), "<em>x</em>");
+
+    let html = "<template id=t><b>x</b></template><p id=a>\n<i>y</i>";
+    let document = parse_html_with_source_positions(ParseOpts::default()).one(html);
+    assert!(document.get_element_by_id("a").is_some());
+    let template = document.select_first("template").unwrap().unwrap().as_node().clone();
+    let contents = template.as_element().unwrap().template_contents.clone().unwrap();
+    let b = contents.first_child().unwrap();
+    let renamed = template.rename(qualname!(html, "div"));
+    let new_contents = renamed.as_element().unwrap().template_contents.clone().unwrap();
+    assert!(contents.first_child().is_none());
+    assert!(new_contents.first_child() == Some(b));
+    let i = document.select_first("i").unwrap().unwrap().as_node().clone();
+    let i_position = i.source_position();
+    assert!(i_position.is_some());
+    let em = i.rename(qualname!(html, "em"));
+    assert_eq!(em.source_position(), i_position);
+    assert_eq!(i.source_position(), None);
+    let p = document.get_element_by_id("a").unwrap().as_node().clone();
+    let span = p.rename(qualname!(html, "span"));
+    assert!(document.get_element_by_id("a").unwrap().as_node() == &span);
+
+    let doctype = document.first_child().unwrap();
+    doctype.replace_with(NodeRef::new_doctype("html", "", "about:legacy-compat"));
+    assert_eq!(document.first_child().unwrap().as_doctype().unwrap().system_id,
+               "about:legacy-compat");
+}
+
+#[test]
+fn root_document() {
+    let document = parse_html().one("<p><b>Foo</b></p>");
+    let b = document.select_first("b").unwrap().unwrap();
+    let text = b.as_node().first_child().unwrap();
+    assert!(text.root() == document);
+    assert!(document.root() == document);
+    assert!(text.document().unwrap().as_node() == &document);
+    assert_eq!(text.document().unwrap().quirks_mode(), QuirksMode::Quirks);
+
+    let p = document.select_first("p").unwrap().unwrap().as_node().make_orphan();
+    assert!(text.root() == p);
+    assert!(text.document().is_none());
+    assert!(p.document().is_none());
+}
+
+#[test]
+fn serialize_attribute_filter() {
+    use std::rc::Rc;
+
+    let html = "<p onclick=a() class=x>1<svg onload=b() xlink:href=#c></svg></p>";
+    let document = parse_html().one(html);
+    let p = document.select_first("p").unwrap().unwrap();
+    let mut opts = SerializeOpts::default();
+    opts.attribute_filter = Some(Rc::new(|element: &QualName, attribute: &QualName| {
+        !attribute.local.starts_with("on") &&
+        !(element.local == atom!("svg") && attribute.ns == ns!(xlink))
+    }));
+    assert_eq!(p.as_node().to_string_with_options(&opts), "<p class=\"x\">1<svg></svg></p>");
+    assert!(p.attributes.borrow().contains("onclick"));
+
+    opts.xml = true;
+    assert_eq!(p.as_node().to_string_with_options(&opts),
+               "<p xmlns=\"http://www.w3.org/1999/xhtml\" class=\"x\">1<svg:svg \
+                xmlns:svg=\"http://www.w3.org/2000/svg\"/></p>");
+    assert!(format!("{:?}", opts).contains("attribute_filter"));
+}
+
+#[test]
+fn retain_children() {
+    let document = parse_html().one("<p><!-- 1 -->2<b>3</b> <!-- 4 --><i>5</i><!-- 6 --></p>");
+    let p = document.select_first("p").unwrap().unwrap();
+    let p = p.as_node();
+    let comment = ::rc::Rc::downgrade(&p.first_child().unwrap().0);
+    p.retain_children(|child| child.as_comment().is_none());
+    assert_eq!(p.to_string(), "<p>2<b>3</b> <i>5</i></p>");
+    assert!(comment.upgrade().is_none());
+    assert!(p.first_child().unwrap().previous_sibling().is_none());
+    assert!(p.last_child().unwrap().next_sibling().is_none());
+    assert_eq!(p.children().rev().count(), 4);
+
+    p.retain_children(|child| child.as_text().map_or(true, |text| !text.borrow().trim().is_empty()));
+    assert_eq!(p.to_string(), "<p>2<b>3</b><i>5</i></p>");
+    p.retain_children(|_| false);
+    assert!(p.first_child().is_none() && p.last_child().is_none());
+}
+
+#[test]
+fn depth() {
+    let document = parse_html().one("<p><b>Foo</b></p><div></div>");
+    let body = document.select_first("body").unwrap().unwrap();
+    let b = document.select_first("b").unwrap().unwrap();
+    let div = document.select_first("div").unwrap().unwrap();
+    let text = b.as_node().first_child().unwrap();
+    assert_eq!(document.depth(), 0);
+    assert_eq!(body.as_node().depth(), 2);
+    assert_eq!(text.depth(), 5);
+    assert_eq!(text.depth_from(body.as_node()), Some(3));
+    assert_eq!(text.depth_from(&text), Some(0));
+    assert_eq!(text.depth_from(div.as_node()), None);
+    assert_eq!(body.as_node().depth_from(&text), None);
+}
+
+#[test]
+fn contains() {
+    let document = parse_html().one("<p><b>Foo</b></p><div></div>");
+    let p = document.select_first("p").unwrap().unwrap();
+    let div = document.select_first("div").unwrap().unwrap();
+    let text = document.select_first("b").unwrap().unwrap().as_node().first_child().unwrap();
+    assert!(p.as_node().contains(&text));
+    assert!(document.contains(&text));
+    assert!(text.contains(&text));
+    assert!(!div.as_node().contains(&text));
+    assert!(!text.contains(p.as_node()));
+    assert!(!document.contains(&NodeRef::new_text("Foo")));
+}
+
+#[test]
+fn text_contents_with() {
+    use text::TextExtractOpts;
+
+    let html = "<table>\n<tr><td>  A  <b>1</b></td><td>B <img alt='[2]' src=x></td></tr>\n\
+                <tr><td>C</td><td><input type=IMAGE title=Go><img src=y></td></tr></table>";
+    let document = parse_html().one(html);
+    let table = document.select_first("table").unwrap().unwrap();
+    let table = table.as_node();
+    let mut opts = TextExtractOpts::default();
+    assert_eq!(table.text_contents_with(&opts), table.text_contents());
+
+    opts.block_separator = " | ".to_owned();
+    opts.collapse_whitespace = true;
+    assert_eq!(table.text_contents_with(&opts), "A 1 | B | C");
+    opts.include_alt_text = true;
+    assert_eq!(table.text_contents_with(&opts), "A 1 | B [2] | C | Go");
+    opts.collapse_whitespace = false;
+    assert_eq!(table.text_contents_with(&opts), "\n |   A  1 | B [2] | \n | C | Go");
+}
+
+#[test]
+fn from_html() {
+    let div = NodeRef::from_html("\n  <div class='a'><span>x</span></div>\n");
+    assert_eq!(div.to_string(), "<div class=\"a\"><span>x</span></div>");
+    assert!(div.parent().is_none());
+    // Table cells are dropped outside of tables.
+    assert!(NodeRef::try_from_html("<td>1</td>").is_err());
+
+    assert!(NodeRef::try_from_html("").is_err());
+    assert!(NodeRef::try_from_html("  ").is_err());
+    assert!(NodeRef::try_from_html("<p>1</p><p>2</p>").is_err());
+    assert!(NodeRef::try_from_html("<p>1</p> text").is_err());
+    assert!(NodeRef::try_from_html("<!-- x --><p>1</p>").is_err());
+    assert!(NodeRef::try_from_html("<b>1</b>").is_ok());
+}
+
+#[test]
+fn reference_counts() {
+    let document = parse_html().one("<p><b>1</b><i>2</i></p>");
+    let p = document.select_first("p").unwrap().unwrap().as_node().clone();
+    // `p` and the `first_child` link from `body`
+    assert_eq!(p.strong_count(), 2);
+    // The `last_child` link from `body` and the `parent` links from two children
+    assert_eq!(p.weak_count(), 3);
+
+    let b = p.first_child().unwrap();
+    p.detach();
+    drop(document);
+    assert_eq!(p.strong_count(), 1);
+    assert_eq!(b.strong_count(), 2);
+    drop(p);
+    assert_eq!(b.strong_count(), 1);
+    assert!(b.parent().is_none());
+}
+
+#[test]
+fn move_children_to() {
+    let document = parse_html().one("<div><i>1</i>2<b>3</b></div><p><u>4</u></p><ul></ul>");
+    let div = document.select_first("div").unwrap().unwrap().as_node().clone();
+    let p = document.select_first("p").unwrap().unwrap().as_node().clone();
+    let ul = document.select_first("ul").unwrap().unwrap().as_node().clone();
+
+    div.move_children_to(&p);
+    assert_eq!(div.to_string(), "<div></div>");
+    assert!(div.first_child().is_none() && div.last_child().is_none());
+    assert_eq!(p.to_string(), "<p><u>4</u><i>1</i>2<b>3</b></p>");
+    assert_eq!(p.children().rev().count(), 4);
+    assert!(p.children().all(|child| child.parent() == Some(p.clone())));
+
+    p.move_children_to_start(&ul);
+    assert_eq!(ul.to_string(), "<ul><u>4</u><i>1</i>2<b>3</b></ul>");
+    div.append(NodeRef::new_text("5"));
+    div.move_children_to_start(&ul);
+    assert_eq!(ul.to_string(), "<ul>5<u>4</u><i>1</i>2<b>3</b></ul>");
+    assert!(ul.first_child().unwrap().previous_sibling().is_none());
+    assert!(ul.first_child().unwrap().next_sibling().unwrap().previous_sibling() ==
+            ul.first_child());
+    assert_eq!(ul.children().rev().count(), 5);
+
+    // Nothing to move, or moving to itself.
+    p.move_children_to(&ul);
+    ul.move_children_to(&ul);
+    assert_eq!(ul.to_string(), "<ul>5<u>4</u><i>1</i>2<b>3</b></ul>");
+}
+
+#[test]
+fn structural_pseudo_classes() {
+    let html = "<table>\n\
+                <tr><th>H</th><th>H</th></tr>\n\
+                <!-- comment --><tr><td>1</td><td>a</td></tr>\n\
+                <tr><td>2</td></tr>\n\
+                <tr><td>3</td><td>c</td></tr>\n\
+                <tr><td>4</td><td>d</td></tr>\n\
+                </table>\
+                <div><span>s1</span><p>p1</p><span>s2</span><p>p2</p><span>s3</span></div>";
+    let document = parse_html().one(html);
+    let texts = |selector: &str| {
+        document.select(selector).unwrap().map(|e| e.text_contents()).collect::<Vec<_>>()
+    };
+    // Text and comment siblings are not counted.
+    assert_eq!(texts("tr:nth-child(even) > td:first-child"), ["1", "3"]);
+    assert_eq!(texts("tr:nth-child(odd) > td:first-child"), ["2", "4"]);
+    assert_eq!(texts("tr:nth-child(2n+3) > :first-child"), ["2", "4"]);
+    assert_eq!(texts("tr:nth-last-child(1) > td:last-child"), ["d"]);
+    assert_eq!(texts("td:only-child"), ["2"]);
+    assert_eq!(texts("tr:first-child > th:nth-child(1)"), ["H"]);
+    assert_eq!(texts("tr:last-child td"), ["4", "d"]);
+
+    assert_eq!(texts("div > span:nth-of-type(2)"), ["s2"]);
+    assert_eq!(texts("div > p:nth-of-type(2n+1)"), ["p1"]);
+    assert_eq!(texts("div > span:nth-last-of-type(1)"), ["s3"]);
+    assert_eq!(texts("div > p:first-of-type, div > p:last-of-type"), ["p1", "p2"]);
+    assert_eq!(texts("div > :only-of-type"), Vec::<String>::new());
+    assert_eq!(texts("div > :nth-child(-n+2)"), ["s1", "p1"]);
+}
+
+#[test]
+fn attribute_selectors() {
+    let html = "<a id=1 href='https://example.com/' data-testid=login-button>1</a>\
+                <a id=2 href='http://example.org/doc.PDF' class='big  external'>2</a>\
+                <p id=3 lang=en-US title=''>3</p>\
+                <p id=4 lang=en>4</p>\
+                <p id=5 lang=english DATA-X=Foo>5</p>";
+    let document = parse_html().one(html);
+    let ids = |selector: &str| {
+        document.select(selector).unwrap().map(|e| e.get_attribute("id").unwrap())
+            .collect::<Vec<_>>().join(" ")
+    };
+    assert_eq!(ids("[href]"), "1 2");
+    assert_eq!(ids("[title]"), "3");
+    assert_eq!(ids("[data-testid=\"login-button\"]"), "1");
+    assert_eq!(ids("[data-testid=login]"), "");
+    assert_eq!(ids("[href^=\"https\"]"), "1");
+    assert_eq!(ids("[href$=\".PDF\"]"), "2");
+    assert_eq!(ids("[href$=\".pdf\"]"), "");
+    assert_eq!(ids("[href*=example]"), "1 2");
+    assert_eq!(ids("[class~=external]"), "2");
+    assert_eq!(ids("[class~=ext]"), "");
+    assert_eq!(ids("[lang|=en]"), "3 4");
+    // Attribute names are case-insensitive in HTML, values are not by default.
+    assert_eq!(ids("[data-x=Foo]"), "5");
+    assert_eq!(ids("[DATA-X=foo]"), "");
+    // The `i` flag makes values ASCII case-insensitive, with every operator.
+    assert_eq!(ids("[data-x=foo i]"), "5");
+    assert_eq!(ids("[LANG=EN-us i]"), "3");
+    assert_eq!(ids("[href^=HTTPS i]"), "1");
+    assert_eq!(ids("[href^=HTTPS]"), "");
+    assert_eq!(ids("[href$=\".pdf\" I]"), "2");
+    assert_eq!(ids("[href*=EXAMPLE i]"), "1 2");
+    assert_eq!(ids("[class~=EXTERNAL i]"), "2");
+    assert_eq!(ids("[class~=EXT i]"), "");
+    assert_eq!(ids("[lang|=EN i]"), "3 4");
+    assert_eq!(ids("[*|DATA-X^=f i]"), "5");
+    assert_eq!(ids("[|href$=PDF i], p:not([lang^=EN- i])"), "2 4 5");
+    assert!(::Selectors::compile("[nope|href^=a i]").is_err());
+    assert!(::Selectors::compile("[href^=a b]").is_err());
+    assert!(::Selectors::compile("[href^=a i").is_err());
+    assert!(::Selectors::compile(":not([href^=a i)").is_err());
+}
+
+#[test]
+fn new_document_with() {
+    let html = NodeRef::new_element_str("html");
+    html.append(NodeRef::new_element_str("head"));
+    html.append(NodeRef::new_element_str("body"));
+    let document = NodeRef::new_document_with(vec![NodeRef::new_doctype("html", "", ""), html]);
+    assert_eq!(document.as_document().unwrap().quirks_mode(), QuirksMode::NoQuirks);
+    assert_eq!(document.to_string(), "<!DOCTYPE html>\n<html><head></head><body></body></html>");
+    assert!(document.deep_eq(&parse_html().one("<!DOCTYPE html>")));
+}
+
+#[test]
+fn html_name_attr_name() {
+    assert_eq!(html_name("div"), qualname!(html, "div"));
+    assert_eq!(attr_name("class"), qualname!("", "class"));
+    assert_eq!(attr_name("data-foo"), QualName::new(ns!(), Atom::from("data-foo")));
+
+    let element = NodeRef::new_element(html_name("p"), vec![(attr_name("id"), "a".to_owned())]);
+    assert!(element.deep_eq(&NodeRef::new_element_str_with_attributes("p", vec![("id", "a")])));
+}
+
+#[test]
+fn preceding_following_text() {
+    let document = parse_html().one("<p>Time: <b>Prep:</b> 10 <!-- x -->min <b>Cook:</b> 1 h</p>");
+    let bold = document.select("b").unwrap().collect::<Vec<_>>();
+    assert_eq!(bold[0].as_node().preceding_text(), "Time:");
+    assert_eq!(bold[0].as_node().following_text(), "10 min");
+    assert_eq!(bold[1].as_node().preceding_text(), "10 min");
+    assert_eq!(bold[1].as_node().following_text(), "1 h");
+
+    let p = document.select_first("p").unwrap().unwrap();
+    assert_eq!(p.as_node().preceding_text(), "");
+    assert_eq!(p.as_node().following_text(), "");
+}
+
+#[test]
+fn shallow_clone() {
+    let document = parse_html().one("<p class=a>Foo<b>Bar</b></p><template><i>1</i></template>");
+    let p = document.select_first("p").unwrap().unwrap();
+    let copy = p.as_node().shallow_clone();
+    assert!(copy != *p.as_node());
+    assert!(copy.is_equal_node(p.as_node()));
+    assert!(copy.parent().is_none() && copy.next_sibling().is_none() && !copy.has_children());
+    copy.as_element().unwrap().attributes.borrow_mut().insert("class", "b".to_owned());
+    assert_eq!(p.attributes.borrow().get("class"), Some("a"));
+
+    let text = p.as_node().first_child().unwrap();
+    let text_copy = text.shallow_clone();
+    text_copy.as_text().unwrap().borrow_mut().push('!');
+    assert_eq!(&*text.as_text().unwrap().borrow(), "Foo");
+
+    let template = document.select_first("template").unwrap().unwrap();
+    let template_copy = template.as_node().shallow_clone();
+    let contents = template_copy.as_element().unwrap().template_contents.clone().unwrap();
+    assert!(contents != *template.template_contents.as_ref().unwrap());
+    assert_eq!(contents.to_string(), "<i>1</i>");
+}
+
+#[test]
+fn scope() {
+    let document = parse_html().one("<div id=a><div id=b><div id=c></div></div>\
+                                     <p><div id=d></div></p><div id=e></div></div>");
+    let a = document.select_first("#a").unwrap().unwrap();
+    let ids = |selectors: &str| {
+        a.as_node().select(selectors).unwrap().map(|element| {
+            element.attributes.borrow().get("id").unwrap().to_owned()
+        }).collect::<Vec<_>>()
+    };
+    assert_eq!(ids(":scope > div"), vec!["b", "d", "e"]);
+    assert_eq!(ids("div"), vec!["a", "b", "c", "d", "e"]);
+    assert_eq!(ids(":scope"), vec!["a"]);
+    assert_eq!(ids(":scope > div > div"), vec!["c"]);
+
+    let selectors = ::Selectors::compile(":scope > div").unwrap();
+    let b = document.select_first("#b").unwrap().unwrap();
+    assert_eq!(b.as_node().select_with(&selectors).count(), 1);
+
+    // Without a scope, `:scope` is the root element.
+    assert_eq!(document.descendants().select(":scope > body").unwrap().count(), 1);
+    assert_eq!(document.select(":scope > body").unwrap().count(), 1);
+    assert_eq!(document.select_with(&::Selectors::compile(":scope").unwrap()).count(), 1);
+    assert!(document.select_first("html").unwrap().unwrap().as_node().matches(":scope").unwrap());
+    assert!(selectors.matches_in_scope(&b, a.as_node()));
+    assert!(!selectors.matches(&b));
+}
+
+#[test]
+fn lowercase_names() {
+    let div = NodeRef::new_element(QualName::new(ns!(html), Atom::from("DIV")), vec![
+        (QualName::new(ns!(), Atom::from("ID")), "A".to_owned()),
+        (QualName::new(ns!(), Atom::from("id")), "b".to_owned()),
+        (QualName::new(ns!(xlink), Atom::from("HREF")), "#C".to_owned()),
+    ]);
+    div.append(NodeRef::new_element(QualName::new(ns!(html), Atom::from("Span")), vec![]));
+    div.append(NodeRef::new_element(qualname!(svg, "clipPath"), vec![]));
+    let document = NodeRef::new_document_with(vec![div.clone()]);
+    assert!(document.select_first("div").unwrap().is_none());
+
+    let div = div.lowercase_names();
+    assert!(div.parent() == Some(document.clone()));
+    assert_eq!(div.to_string(),
+               "<div id=\"A\" xlink:href=\"#C\"><span></span><clippath></clippath></div>");
+    assert_eq!(div.as_element().unwrap().name, qualname!(html, "div"));
+    assert_eq!(div.first_child().unwrap().as_element().unwrap().name, qualname!(html, "span"));
+    assert_eq!(div.last_child().unwrap().as_element().unwrap().name.ns, ns!(svg));
+    assert!(document.select_first("div > span").unwrap().is_some());
+
+    let document = parse_html().one("<p>");
+    let p = document.select_first("p").unwrap().unwrap().as_node().clone();
+    p.append(NodeRef::new_element(qualname!(html, "i"), vec![
+        (QualName::new(ns!(), Atom::from("ID")), "x".to_owned()),
+    ]));
+    assert!(document.get_element_by_id("x").is_none());
+    assert!(p.lowercase_names() == p);
+    assert!(document.get_element_by_id("x").is_some());
+
+    let copy = div.clone_subtree();
+    let again = div.lowercase_names();
+    assert!(again == div);
+    assert!(again.deep_eq(&copy));
+}
+
+#[test]
+fn select_collect() {
+    let html = "<ul><li>1<ul><li>2</li></ul></li><li>3</li><li>4</li></ul>";
+    let document = parse_html().one(html);
+    let items = document.select_collect("li").unwrap();
+    assert_eq!(items.len(), 4);
+    for item in &items {
+        item.as_node().detach();
+    }
+    assert_eq!(document.select("li").unwrap().count(), 0);
+    assert_eq!(items[0].as_node().to_string(), "<li>1<ul></ul></li>");
+
+    // Detaching during lazy iteration stops at the end of the first match.
+    let document = parse_html().one(html);
+    let mut count = 0;
+    for item in document.select("li").unwrap() {
+        item.as_node().detach();
+        count += 1;
+    }
+    assert_eq!(count, 2);
+    assert_eq!(document.select("li").unwrap().count(), 2);
+}
+
+#[test]
+fn parse_fragment_quirks_mode() {
+    let html = "<p>Foo<table></table>";
+    let fragment = parse_fragment_in(qualname!(html, "body"), vec![], QuirksMode::NoQuirks).one(html);
+    assert_eq!(fragment.first_child().unwrap().to_string(),
+               "<html><p>Foo</p><table></table></html>");
+    let fragment = parse_fragment_in(qualname!(html, "body"), vec![], QuirksMode::Quirks).one(html);
+    assert_eq!(fragment.first_child().unwrap().to_string(),
+               "<html><p>Foo<table></table></p></html>");
+
+    let document = parse_html().one("<div></div>");
+    assert_eq!(document.as_document().unwrap().quirks_mode(), QuirksMode::Quirks);
+    let div = document.select_first("div").unwrap().unwrap();
+    div.as_node().set_inner_html(html);
+    assert_eq!(div.as_node().to_string(), "<div><p>Foo<table></table></p></div>");
+
+    let document = parse_html().one("<!DOCTYPE html><div></div>");
+    let div = document.select_first("div").unwrap().unwrap();
+    div.as_node().set_inner_html(html);
+    assert_eq!(div.as_node().to_string(), "<div><p>Foo</p><table></table></div>");
+}
+
+#[test]
+fn first_last_element_child() {
+    let document = parse_html().one("<div> <!-- a --><p>1</p>x<p>2</p><!-- b --> </div><div>text</div>");
+    let mut divs = document.select("div").unwrap();
+    let div = divs.next().unwrap();
+    assert_eq!(div.as_node().first_element_child().unwrap().text_contents(), "1");
+    assert_eq!(div.as_node().last_element_child().unwrap().text_contents(), "2");
+
+    let div = divs.next().unwrap();
+    assert!(div.as_node().first_element_child().is_none());
+    assert!(div.as_node().last_element_child().is_none());
+    assert!(NodeRef::new_text("").first_element_child().is_none());
+}
+
+#[test]
+fn has_attribute_attribute_count() {
+    let document = parse_html().one("<p id=a hidden></p><svg><a xlink:href=#b></a></svg>");
+    let p = document.select_first("p").unwrap().unwrap();
+    assert_eq!(p.attribute_count(), 2);
+    assert!(p.has_attribute("id"));
+    assert!(p.has_attribute("hidden"));
+    assert!(!p.has_attribute("ID"));
+    assert!(!p.has_attribute("class"));
+
+    let a = document.select_first("a").unwrap().unwrap();
+    assert_eq!(a.attribute_count(), 1);
+    assert!(!a.has_attribute("href"));
+    assert_eq!(a.get_attribute("href"), None);
+}
+
+#[test]
+fn append_text_comment() {
+    let p = NodeRef::new_element_str("p");
+    let text = p.append_text("Foo");
+    assert!(p.first_child() == Some(text.clone()));
+    let comment = p.append_comment(" x ");
+    assert!(p.last_child() == Some(comment));
+    let merged = p.append_text_merged("Bar");
+    assert!(merged != text);
+    assert!(p.append_text_merged(" baz") == merged);
+    assert_eq!(p.to_string(), "<p>Foo<!-- x -->Bar baz</p>");
+    assert_eq!(p.children().count(), 3);
+    p.append_text("!");
+    assert_eq!(p.children().count(), 4);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_json() {
+    use serde_json;
+
+    let p = NodeRef::from_html("<p class=a>Hi</p>");
+    assert_eq!(serde_json::to_string(&p).unwrap(),
+               "{\"type\":\"element\",\"namespace\":\"http://www.w3.org/1999/xhtml\",\"name\":\"p\",\
+                \"attributes\":[{\"namespace\":\"\",\"name\":\"class\",\"value\":\"a\"}],\
+                \"children\":[{\"type\":\"text\",\"data\":\"Hi\"}]}");
+
+    let html = "<!DOCTYPE html><title>T</title><p id=b class=a>Foo<!-- bar --><b>Baz</b></p>\
+                <template><i>1</i></template><svg><a xlink:href=#c></a></svg>";
+    let document = parse_html().one(html);
+    let json = serde_json::to_string(&document).unwrap();
+    let copy: NodeRef = serde_json::from_str(&json).unwrap();
+    assert!(copy.deep_eq(&document));
+    assert_eq!(copy.to_string(), document.to_string());
+    assert_eq!(serde_json::to_string(&copy).unwrap(), json);
+
+    assert!(serde_json::from_str::<NodeRef>("{\"type\":\"text\"}").is_err());
+    assert!(serde_json::from_str::<NodeRef>("{\"type\":\"foo\"}").is_err());
+}
+
+#[test]
+fn node_type_predicates() {
+    let document = parse_html().one("<!DOCTYPE html><!-- a --><p>Foo</p><svg><a></a></svg>");
+    assert!(document.is_document() && !document.is_element());
+    let doctype = document.first_child().unwrap();
+    assert!(doctype.is_doctype() && !doctype.is_comment());
+    let comment = doctype.next_sibling().unwrap();
+    assert!(comment.is_comment() && !comment.is_text());
+
+    let p = document.select_first("p").unwrap().unwrap().as_node().clone();
+    assert!(p.is_element() && !p.is_document());
+    assert!(p.is_element_named("p"));
+    assert!(p.is_element_named("P"));
+    assert!(!p.is_element_named("div"));
+    let text = p.first_child().unwrap();
+    assert!(text.is_text() && !text.is_element() && !text.is_element_named("p"));
+
+    let svg_a = document.select_first("a").unwrap().unwrap();
+    assert!(svg_a.as_node().is_element() && !svg_a.as_node().is_element_named("a"));
+    assert_eq!(document.descendants().filter(|node| node.is_element_named("body")).count(), 1);
+}
+
+#[cfg(feature = "url")]
+#[test]
+fn resolve_urls() {
+    extern crate url;
+
+    let html = "<a href=b/c?d#e>1</a><img src=/i.png srcset=\"i.png 1x,  /j.png 2x,k.png\">\
+                <form action=\"\"></form><a href=\"http://[::1\">2</a><p href=x></p>\
+                <svg><a xlink:href=x href=y></a></svg>";
+    let base = url::Url::parse("http://example.com/a/b.html").unwrap();
+    let document = parse_html().one(html);
+    document.resolve_urls(&base);
+    let body = document.select_first("body").unwrap().unwrap();
+    assert_eq!(body.as_node().inner_html(),
+               "<a href=\"http://example.com/a/b/c?d#e\">1</a>\
+                <img src=\"http://example.com/i.png\" srcset=\"http://example.com/a/i.png 1x, \
+                http://example.com/j.png 2x, http://example.com/a/k.png\">\
+                <form action=\"http://example.com/a/b.html\"></form><a href=\"http://[::1\">2</a>\
+                <p href=\"x\"></p><svg><a xlink:href=\"x\" href=\"y\"></a></svg>");
+
+    let document = parse_html().one("<base href=/x/><a href=y>1</a>");
+    document.resolve_urls(&base);
+    let a = document.select_first("a").unwrap().unwrap();
+    assert_eq!(a.attributes.borrow().get("href"), Some("http://example.com/x/y"));
+}
+
+#[test]
+fn clone_children_into() {
+    let document = parse_html().one("<template><li>a</li><li>b</li></template>\
+                                     <ul id=x><li>0</li></ul><ul id=y></ul>");
+    let template = document.select_first("template").unwrap().unwrap();
+    let contents = template.template_contents.as_ref().unwrap();
+    let x = document.select_first("#x").unwrap().unwrap();
+    let y = document.select_first("#y").unwrap().unwrap();
+    contents.clone_children_into(x.as_node());
+    contents.clone_children_into(y.as_node());
+    assert_eq!(x.as_node().to_string(), "<ul id=\"x\"><li>0</li><li>a</li><li>b</li></ul>");
+    assert_eq!(y.as_node().to_string(), "<ul id=\"y\"><li>a</li><li>b</li></ul>");
+
+    y.as_node().first_child().unwrap().first_child().unwrap().as_text().unwrap()
+        .borrow_mut().push('!');
+    y.as_node().last_child().unwrap().detach();
+    assert_eq!(contents.to_string(), "<li>a</li><li>b</li>");
+    assert_eq!(x.as_node().to_string(), "<ul id=\"x\"><li>0</li><li>a</li><li>b</li></ul>");
+
+    x.as_node().clone_children_into(x.as_node());
+    assert_eq!(x.as_node().children().count(), 6);
+}
+
+#[test]
+fn selectors_matches_single_element() {
+    let selectors = ::Selectors::compile("ul > li:first-child, .a").unwrap();
+    let document = parse_html().one("<ul><li>1</li><li class=a>2</li><li>3</li></ul>");
+    let items = document.select("li").unwrap().map(|li| li.as_node().clone()).collect::<Vec<_>>();
+    let matches = |node: &NodeRef| {
+        node.clone().into_element_ref().map_or(false, |element| selectors.matches(&element))
+    };
+    assert_eq!(items.iter().map(&matches).collect::<Vec<_>>(), vec![true, true, false]);
+    assert!(!matches(&items[0].first_child().unwrap()));
+
+    // Combinators see the live tree.
+    let first = items[0].clone();
+    first.detach();
+    assert!(!matches(&first));
+    assert!(matches(&items[1]));
+    let ol = NodeRef::new_element_str("ol");
+    ol.append(items[2].clone());
+    assert!(!matches(&items[2]));
+}
+
+#[test]
+fn max_depth() {
+    let bomb = format!("{}x{}", iter::repeat("<div>").take(10000).collect::<String>(),
+                       iter::repeat("</div>").take(10000).collect::<String>());
+    let opts = ParseOpts { max_depth: Some(5), ..ParseOpts::default() };
+    let document = parse_html_with_options(opts).one(bomb);
+    assert_eq!(document.descendants().map(|node| node.depth()).max(), Some(5));
+    let divs = document.select("div").unwrap().collect::<Vec<_>>();
+    assert_eq!(divs.len(), 10000);
+    assert_eq!(divs[1].as_node().children().count(), 9999);
+    assert_eq!(divs[1].as_node().text_contents(), "x");
+    assert_eq!(divs[2].as_node().children().count(), 0);
+
+    // Content that is not too deep is unaffected.
+    let html = "<div><p>1<b>2<i>3</i></b></p><ul><li>4</ul></div>";
+    let opts = ParseOpts { max_depth: Some(7), ..ParseOpts::default() };
+    assert!(parse_html_with_options(opts).one(html).deep_eq(&parse_html().one(html)));
+    let opts = ParseOpts { max_depth: Some(4), ..ParseOpts::default() };
+    let body = parse_html_with_options(opts).one(html).select_first("body").unwrap().unwrap();
+    assert_eq!(body.as_node().inner_html(), "<div><p></p>1<b></b>2<i></i>3<ul></ul><li></li>4</div>");
+}
+
+#[test]
+fn new_element_str() {
+    let div = NodeRef::new_element_str("DIV");
+    let parsed = NodeRef::from_html("<DIV></DIV>");
+    assert_eq!(div.as_element().unwrap().name, parsed.as_element().unwrap().name);
+    assert!(div.deep_eq(&parsed));
+
+    let p = NodeRef::new_element_str_with_attributes("p", vec![("Class", "a"), ("id", "b")]);
+    div.append(p);
+    assert_eq!(div.to_string(), "<div><p class=\"a\" id=\"b\"></p></div>");
+    assert!(div.deep_eq(&NodeRef::from_html("<div><p CLASS=a id=b></p></div>")));
+    assert_eq!(div.matches("div"), Ok(true));
+    assert_eq!(div.select("div > p.a#b").unwrap().count(), 1);
+}
+
+#[test]
+fn diff() {
+    use diff::DiffOp;
+
+    let before = parse_html().one("<div id=x class=a><p>Foo</p><p>Bar<!-- 1 --></p></div>");
+    let after = parse_html().one("<div id=x title=t><h1>Title</h1><p>Foo</p>\
+                                  <p>Baz<!-- 2 --></p><ul></ul></div>");
+    assert!(before.diff(&before.clone_subtree()).is_empty());
+
+    let ops = before.diff(&after);
+    let h1 = after.select_first("h1").unwrap().unwrap().as_node().clone();
+    let ul = after.select_first("ul").unwrap().unwrap().as_node().clone();
+    assert_eq!(ops, vec![
+        DiffOp::Attribute {
+            path: "html > body:nth-child(2) > div".to_owned(),
+            name: qualname!("", "class"),
+            old: Some("a".to_owned()),
+            new: None,
+        },
+        DiffOp::Attribute {
+            path: "html > body:nth-child(2) > div".to_owned(),
+            name: qualname!("", "title"),
+            old: None,
+            new: Some("t".to_owned()),
+        },
+        DiffOp::Insert { path: "html > body:nth-child(2) > div".to_owned(), index: 0, node: h1 },
+        DiffOp::Insert { path: "html > body:nth-child(2) > div".to_owned(), index: 3, node: ul },
+        DiffOp::Text {
+            path: "html > body:nth-child(2) > div > p:nth-child(2)".to_owned(),
+            index: 0,
+            old: "Bar".to_owned(),
+            new: "Baz".to_owned(),
+        },
+        DiffOp::Text {
+            path: "html > body:nth-child(2) > div > p:nth-child(2)".to_owned(),
+            index: 1,
+            old: " 1 ".to_owned(),
+            new: " 2 ".to_owned(),
+        },
+    ]);
+
+    let ops = after.diff(&before);
+    assert_eq!(ops.iter().filter(|op| match **op { DiffOp::Remove { .. } => true, _ => false })
+                  .count(), 2);
+
+    let a = NodeRef::new_text("a");
+    let b = NodeRef::new_element_str("b");
+    assert_eq!(a.diff(&b), vec![
+        DiffOp::Remove { path: String::new(), index: 0, node: a.clone() },
+        DiffOp::Insert { path: String::new(), index: 0, node: b.clone() },
+    ]);
+}
+
+#[test]
+fn into_sendable() {
+    use std::thread;
+
+    let html = "<!DOCTYPE html><p class=a id=b>Foo<!-- bar --><b>Baz</b></p>\
+                <template><i>1</i></template>";
+    let document = parse_html().one(html);
+    let sendable = document.clone().into_sendable();
+    let sendable = thread::spawn(move || {
+        assert_eq!(sendable.children.len(), 2);
+        sendable
+    }).join().unwrap();
+    let copy = sendable.into_node();
+    assert!(copy.deep_eq(&document));
+    assert_eq!(copy.to_string(), document.to_string());
+    assert_eq!(copy.as_document().unwrap().quirks_mode(), QuirksMode::NoQuirks);
+
+    let p = document.select_first("p").unwrap().unwrap().as_node().clone();
+    let p_copy = p.clone().into_sendable().into_node();
+    assert!(p_copy.parent().is_none());
+    assert_eq!(p_copy.to_string(), p.to_string());
+}
+
+#[test]
+fn whitespace_only() {
+    let html = "<div id=a></div><div id=b> \n\u{A0}\u{2003}</div><div id=c> x </div>\
+                <div id=d> <!-- --> </div><div id=e> <span> </span> </div>";
+    let document = parse_html().one(html);
+    let div = |id: &str| document.get_element_by_id(id).unwrap().as_node().clone();
+    assert!(!div("a").has_children());
+    assert!(div("a").is_whitespace_only());
+    assert!(div("b").has_children());
+    assert!(div("b").is_whitespace_only());
+    assert!(div("b").first_child().unwrap().is_whitespace_only());
+    assert!(!div("c").is_whitespace_only());
+    assert!(!div("c").first_child().unwrap().is_whitespace_only());
+    assert!(!div("d").is_whitespace_only());
+    assert!(!div("e").is_whitespace_only());
+    assert!(div("e").select_first("span").unwrap().unwrap().as_node().is_whitespace_only());
+}
+
+#[test]
+fn display() {
+    let document = parse_html().one("<p class=a>Café &amp; <b>bar</b></p>");
+    let p = document.select_first("p").unwrap().unwrap();
+    let expected = "<p class=\"a\">Café &amp; <b>bar</b></p>";
+    assert_eq!(format!("{}", p.as_node()), expected);
+    assert_eq!(format!("[{}]", p.as_node().first_child().unwrap()), "[Café &amp; ]");
+    assert_eq!(p.as_node().to_string(), expected);
+    assert!(format!("{:?}", p.as_node()).contains(" @ "));
+}
+
+#[test]
+fn parse_fragment_nodes() {
+    let nodes = ::parse_fragment_nodes(qualname!(html, "tbody"), vec![],
+                                       "<tr><td>1</td></tr> <!-- x --><tr><td>2");
+    assert_eq!(nodes.len(), 4);
+    assert!(nodes.iter().all(|node| node.parent().is_none()));
+    assert!(nodes.iter().all(|node| node.previous_sibling().is_none() &&
+                                     node.next_sibling().is_none()));
+    assert_eq!(nodes.iter().map(|node| node.to_string()).collect::<Vec<_>>(),
+               ["<tr><td>1</td></tr>", " ", "<!-- x -->", "<tr><td>2</td></tr>"]);
+
+    let nodes = ::parse_fragment_nodes(qualname!(html, "body"), vec![], "<tr><td>1</td></tr>");
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0].to_string(), "1");
+
+    let table = NodeRef::new_element(qualname!(html, "table"), vec![]);
+    table.append_children(::parse_fragment_nodes(qualname!(html, "table"), vec![], "<tr>"));
+    assert_eq!(table.to_string(), "<table><tbody><tr></tr></tbody></table>");
+}
+
+#[test]
+fn siblings() {
+    let document = parse_html().one("<div><i>1</i>2<b>3</b><!-- 4 --><u>5</u></div>");
+    let div = document.select_first("div").unwrap().unwrap();
+    let children = div.as_node().children().collect::<Vec<_>>();
+    assert_eq!(children.len(), 5);
+
+    let b = &children[2];
+    assert!(b.following_siblings().collect::<Vec<_>>() == &children[3..]);
+    assert!(b.preceding_siblings().collect::<Vec<_>>() ==
+            [children[1].clone(), children[0].clone()]);
+    assert_eq!(children[0].preceding_siblings().count(), 0);
+    assert_eq!(children[4].following_siblings().count(), 0);
+    assert_eq!(children[4].preceding_siblings().count(), 4);
+    assert_eq!(children[0].following_siblings().count(), 4);
+    assert_eq!(children[1].preceding_siblings().next_back().unwrap().to_string(), "<i>1</i>");
+
+    let detached = NodeRef::new_text("x");
+    assert_eq!(detached.preceding_siblings().count(), 0);
+    assert_eq!(detached.following_siblings().count(), 0);
+
+    // Remove everything after a marker.
+    for sibling in b.following_siblings().collect::<Vec<_>>() {
+        sibling.detach()
+    }
+    assert_eq!(div.as_node().to_string(), "<div><i>1</i>2<b>3</b></div>");
+}
+
+#[test]
+fn following_preceding() {
+    let document = parse_html().one(
+        "<div><h2>A</h2><p>1<b>2</b></p><h2>B</h2><p>3</p></div><p>4</p>");
+    let body = document.select_first("body").unwrap().unwrap();
+    let first_p = document.select_first("p").unwrap().unwrap();
+    let b = document.select_first("b").unwrap().unwrap();
+    let text = |nodes: Vec<NodeRef>| {
+        nodes.iter().map(|node| match node.as_text() {
+            Some(text) => text.borrow().clone(),
+            None => format!("<{}>", node.as_element().unwrap().name.local),
+        }).collect::<Vec<_>>().join(" ")
+    };
+
+    assert_eq!(text(first_p.as_node().following().collect()), "<h2> B <p> 3 <p> 4");
+    assert_eq!(text(b.as_node().following().collect()), "<h2> B <p> 3 <p> 4");
+    assert_eq!(text(b.as_node().preceding().collect()), "1 A <h2> <head>");
+    assert_eq!(text(body.as_node().preceding().collect()), "<head>");
+    assert_eq!(body.as_node().following().count(), 0);
+    assert_eq!(document.following().count(), 0);
+    assert_eq!(document.preceding().count(), 0);
+
+    // Everything after the first heading, until the next one.
+    let h2 = document.select_first("h2").unwrap().unwrap();
+    let section = h2.as_node().following()
+        .take_while(|node| node.as_element().map_or(true, |e| e.name.local != atom!("h2")))
+        .filter(|node| node.parent() == h2.as_node().parent())
+        .collect::<Vec<_>>();
+    assert_eq!(section.len(), 1);
+    assert_eq!(section[0].to_string(), "<p>1<b>2</b></p>");
+}
+
+#[test]
+fn attributes_iter() {
+    let document = parse_html().one("<svg><a id=x xlink:href=y class=z></a></svg>");
+    let a = document.select_first("a").unwrap().unwrap();
+    let attributes = a.attributes_iter();
+    let pairs = attributes.iter().collect::<Vec<_>>();
+    assert_eq!(pairs, [("id", "x"), ("class", "z")]);
+    drop(pairs);
+    drop(attributes);
+
+    let mut names = Vec::new();
+    for (name, _) in &a.attributes_iter() {
+        names.push(name.to_owned())
+    }
+    assert_eq!(names, ["id", "class"]);
+
+    let attributes = a.attributes_iter();
+    assert_eq!(attributes.iter_qualified().count(), 3);
+    assert_eq!(attributes.get("class"), Some("z"));
+    drop(attributes);
+    a.attributes.borrow_mut().insert("title", "t".to_owned());
+    assert_eq!(a.attributes_iter().iter().last(), Some(("title", "t")));
+}
+
+#[test]
+fn node_data_ref() {
+    use node_data_ref::NodeDataRef;
+    use tree::Node;
+
+    let document = parse_html().one("<p class=a>Foo</p>");
+    let p = document.select_first("p").unwrap().unwrap();
+    assert_eq!(p.name.local, atom!("p"));
+    assert_eq!(&*p.attributes.borrow().get("class").unwrap(), "a");
+
+    let text = p.as_node().first_child().unwrap();
+    assert!(NodeDataRef::new_opt(text.clone(), Node::as_element).is_none());
+    let text_ref = NodeDataRef::new_opt(text.clone(), Node::as_text).unwrap();
+    assert_eq!(&*text_ref.borrow(), "Foo");
+    assert!(*text_ref.as_node() == text);
+
+    // A `NodeDataRef` keeps its node alive.
+    let copy = p.clone();
+    drop(document);
+    drop(p);
+    text_ref.as_node().detach();
+    assert_eq!(copy.name.local, atom!("p"));
+    assert_eq!(copy.as_node().to_string(), "<p class=\"a\"></p>");
+    assert_eq!(&*text_ref.borrow(), "Foo");
+}
+
+#[test]
+fn set_quirks_mode() {
+    let document = NodeRef::new_document();
+    assert_eq!(document.as_document().unwrap().quirks_mode(), QuirksMode::NoQuirks);
+    document.as_document().unwrap().set_quirks_mode(QuirksMode::LimitedQuirks);
+    assert_eq!(document.as_document().unwrap().quirks_mode(), QuirksMode::LimitedQuirks);
+    assert_eq!(document.clone_subtree().as_document().unwrap().quirks_mode(),
+               QuirksMode::LimitedQuirks);
+
+    let document = parse_html().one("<p>Foo");
+    assert_eq!(document.as_document().unwrap().quirks_mode(), QuirksMode::Quirks);
+    document.as_document().unwrap().set_quirks_mode(QuirksMode::NoQuirks);
+    assert_eq!(document.as_document().unwrap().quirks_mode(), QuirksMode::NoQuirks);
+    assert_eq!(document.to_string(), "<html><head></head><body><p>Foo</p></body></html>");
+}
+
+#[test]
+fn sanitize() {
+    use std::collections::{HashMap, HashSet};
+
+    let html = "<div class=x onclick=evil()><p id=a style=s>Hello <blink>big</blink> \
+                <a href=' jav&#x09;aScript:alert(1)' title=t>world</a><!-- c -->\
+                <a href=/ok>ok</a></p><script>alert(2)</script><img src=x.png></div>";
+    let allowed_elements = ["div", "p", "a"].iter().cloned().collect::<HashSet<_>>();
+    let mut allowed_attributes = HashMap::new();
+    allowed_attributes.insert("a", ["href", "title"].iter().cloned().collect::<HashSet<_>>());
+    allowed_attributes.insert("*", ["class", "id"].iter().cloned().collect::<HashSet<_>>());
+
+    let document = parse_html().one(html);
+    let body = document.select_first("body").unwrap().unwrap();
+    body.as_node().sanitize(&allowed_elements, &allowed_attributes, true);
+    assert_eq!(body.as_node().inner_html(),
+               "<div class=\"x\"><p id=\"a\">Hello big <a title=\"t\">world</a>\
+                <a href=\"/ok\">ok</a></p></div>");
+
+    let document = parse_html().one(html);
+    let body = document.select_first("body").unwrap().unwrap();
+    body.as_node().sanitize(&allowed_elements, &HashMap::new(), false);
+    assert_eq!(body.as_node().inner_html(),
+               "<div><p>Hello  <a>world</a><a>ok</a></p></div>");
+}
+
+#[test]
+fn insert_children_before_after() {
+    let document = parse_html().one("<p><i>1</i><b>2</b></p><div><u>3</u><s>4</s></div>");
+    let p = document.select_first("p").unwrap().unwrap();
+    let b = document.select_first("b").unwrap().unwrap();
+    let div = document.select_first("div").unwrap().unwrap();
+    let moved = div.as_node().children().collect::<Vec<_>>();
+
+    b.as_node().insert_children_before(moved.clone());
+    assert_eq!(p.as_node().to_string(), "<p><i>1</i><u>3</u><s>4</s><b>2</b></p>");
+    assert_eq!(div.as_node().to_string(), "<div></div>");
+
+    b.as_node().insert_children_after(moved.clone());
+    assert_eq!(p.as_node().to_string(), "<p><i>1</i><b>2</b><u>3</u><s>4</s></p>");
+    assert!(p.as_node().last_child().unwrap() == moved[1]);
+    assert!(moved[0].previous_sibling().unwrap() == *b.as_node());
+    assert!(moved[1].next_sibling().is_none());
+    let children = p.as_node().children().collect::<Vec<_>>();
+    let reversed = p.as_node().children().rev().collect::<Vec<_>>();
+    assert!(children.iter().rev().eq(reversed.iter()));
+
+    let i = p.as_node().first_child().unwrap();
+    i.insert_children_after(vec![moved[1].clone(), i.clone(), moved[0].clone()]);
+    assert_eq!(p.as_node().to_string(), "<p><i>1</i><s>4</s><u>3</u><b>2</b></p>");
+    i.insert_children_before(vec![]);
+    i.insert_children_after(vec![]);
+    assert_eq!(p.as_node().to_string(), "<p><i>1</i><s>4</s><u>3</u><b>2</b></p>");
+    assert!(p.as_node().first_child().unwrap() == i);
+}
+
+#[test]
+fn get_elements_by_tag_name() {
+    let html = "<P>1</P><div><p>2</p></div><svg><foreignObject><p>3</p></foreignObject></svg>";
+    let document = parse_html().one(html);
+    let texts = |name: &str| document.get_elements_by_tag_name(name).iter()
+        .map(|element| element.text_contents()).collect::<Vec<_>>();
+    assert_eq!(texts("p"), ["1", "2", "3"]);
+    assert_eq!(texts("P"), ["1", "2", "3"]);
+    assert_eq!(texts("foreignObject"), ["3"]);
+    assert!(texts("foreignobject").is_empty());
+    assert!(texts("span").is_empty());
+    assert_eq!(document.get_elements_by_tag_name("*").len(), 9);
+
+    let div = document.select_first("div").unwrap().unwrap();
+    assert_eq!(div.as_node().get_elements_by_tag_name("p").len(), 1);
+    assert!(div.as_node().get_elements_by_tag_name("div").is_empty());
+}
+
+#[test]
+fn minify() {
+    use minify::MinifyOpts;
+
+    let html = "<!DOCTYPE html>\n<html>\n<head>\n  <title> Foo </title>\n  <!-- x -->\n\
+                <!--[if IE]><p>IE</p><![endif]-->\n</head>\n<body>\n\
+                <div>\n  <p>  Some   <b> bold </b>\n text </p>\n\n  <p>a<!-- y -->  b</p>\n</div>\n\
+                <pre>\n  keep   <!-- z -->\n  this  </pre>\n\
+                <script> var  a; </script>\n</body>\n</html>";
+    let document = parse_html().one(html);
+    document.minify(MinifyOpts::default());
+    assert_eq!(document.to_string(),
+               "<!DOCTYPE html>\n<html><head><title>Foo</title>\
+                <!--[if IE]><p>IE</p><![endif]--></head><body>\
+                <div><p>Some <b> bold </b> text</p><p>a b</p></div>\
+                <pre>  keep   <!-- z -->\n  this  </pre><script> var  a; </script></body></html>");
+
+    let document = parse_html().one(html);
+    document.minify(MinifyOpts {
+        remove_comments: false,
+        remove_block_whitespace: false,
+        ..MinifyOpts::default()
+    });
+    assert_eq!(document.select_first("div").unwrap().unwrap().as_node().to_string(),
+               "<div> <p> Some <b> bold </b> text </p> <p>a<!-- y --> b</p> </div>");
+
+    let document = parse_html().one(html);
+    let pre = document.select_first("pre").unwrap().unwrap();
+    pre.as_node().minify(MinifyOpts::default());
+    assert_eq!(pre.as_node().to_string(), "<pre>  keep   <!-- z -->\n  this  </pre>");
+}
+
+#[test]
+fn index_in_parent() {
+    let document = parse_html().one("<div>a<i>1</i><!-- b --><b>2</b>c<u>3</u></div>");
+    let div = document.select_first("div").unwrap().unwrap();
+    let indices = div.as_node().children()
+        .map(|child| (child.index_in_parent(), child.element_index_in_parent()))
+        .collect::<Vec<_>>();
+    assert_eq!(indices, [
+        (Some(0), None), (Some(1), Some(0)), (Some(2), None),
+        (Some(3), Some(1)), (Some(4), None), (Some(5), Some(2)),
+    ]);
+    let u = document.select_first("u").unwrap().unwrap();
+    let n = u.as_node().element_index_in_parent().unwrap() + 1;
+    assert!(document.select_first(&format!("div > :nth-child({})", n)).unwrap().unwrap()
+            .as_node() == u.as_node());
+    assert_eq!(document.index_in_parent(), None);
+    assert_eq!(NodeRef::new_text("x").index_in_parent(), None);
+}
+
+#[test]
+fn map_attributes() {
+    let document = parse_html().one("<a href=foo title=Foo class=x>");
+    let a = document.select_first("a").unwrap().unwrap();
+    a.map_attribute("href", |href| format!("http://example.com/{}", href));
+    a.map_attribute("src", |_| panic!("not called"));
+    // The closure can read the attributes.
+    a.map_attribute("title", |title| format!("{} ({})", title, a.get_attribute("class").unwrap()));
+    assert_eq!(a.as_node().to_string(),
+               r#"<a href="http://example.com/foo" title="Foo (x)" class="x"></a>"#);
+
+    a.map_all_attributes(|name, value| {
+        assert!(a.get_attribute("class").is_some());
+        if name.local == atom!("class") {
+            None
+        } else {
+            Some(value.to_uppercase())
+        }
+    });
+    assert_eq!(a.as_node().to_string(),
+               r#"<a href="HTTP://EXAMPLE.COM/FOO" title="FOO (X)"></a>"#);
+}
+
+#[test]
+fn set_attributes() {
+    let document = parse_html().one("<p id=a class=b>");
+    let p = document.select_first("p").unwrap().unwrap();
+    p.set_attributes_str(vec![("title", "t"), ("id", "c"), ("lang", "en")]);
+    assert_eq!(p.as_node().to_string(),
+               "<p id=\"c\" class=\"b\" title=\"t\" lang=\"en\"></p>");
+    p.set_attributes(vec![
+        (qualname!(xml, "lang"), "fr".to_owned()),
+        (qualname!("", "class"), "d".to_owned()),
+    ]);
+    assert_eq!(p.as_node().to_string(),
+               "<p id=\"c\" class=\"d\" title=\"t\" lang=\"en\" xml:lang=\"fr\"></p>");
+    p.set_attributes_str(Vec::new());
+    assert_eq!(p.attributes.borrow().list.len(), 5);
+}
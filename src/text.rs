@@ -0,0 +1,225 @@
+use selectors::matching::SELECTOR_WHITESPACE;
+use std::ascii::AsciiExt;
+use std::collections::HashSet;
+use string_cache::Atom;
+
+use iter::NodeEdge;
+use serializer::default_inline_elements;
+use tree::{NodeRef, ElementData};
+
+/// Options for `NodeRef::text_contents_with`.
+///
+/// The default options give the same result as `text_contents`.
+#[derive(Debug, Clone, Default)]
+pub struct TextExtractOpts {
+    /// A string written between the text of different block elements,
+    /// such as `"\n"` or `" | "`.
+    ///
+    /// Block elements are the HTML elements that are not phrasing content:
+    /// all except `a`, `abbr`, `audio`, `b`, `bdi`, `bdo`, `big`, `br`, `button`, `canvas`,
+    /// `cite`, `code`, `data`, `datalist`, `del`, `dfn`, `em`, `embed`, `font`, `i`,
+    /// `iframe`, `img`, `input`, `ins`, `kbd`, `label`, `map`, `mark`, `math`, `meter`,
+    /// `nobr`, `object`, `output`, `picture`, `progress`, `q`, `ruby`, `s`, `samp`,
+    /// `select`, `small`, `span`, `strike`, `strong`, `sub`, `sup`, `svg`, `textarea`,
+    /// `time`, `tt`, `u`, `var`, `video`, and `wbr`.
+    /// The separator is only written between two pieces of text,
+    /// never at the start or end, and never twice in a row.
+    /// The default is an empty string.
+    pub block_separator: String,
+
+    /// Whether to include the text of the `alt` attribute of `<img>`,
+    /// `<area>`, and `<input type=image>` elements, or their `title` if there is no `alt`.
+    /// The default is `false`.
+    pub include_alt_text: bool,
+
+    /// Whether to replace each run of whitespace with a single space,
+    /// and remove whitespace at the start and end and around separators.
+    /// The default is `false`.
+    pub collapse_whitespace: bool,
+}
+
+impl NodeRef {
+    /// Return the concatenation of all text nodes in this subtree, in tree order,
+    /// with the given options.
+    ///
+    /// Unlike `inner_text`, this does not skip any element,
+    /// and keeps the contents of `<pre>` like any other.
+    pub fn text_contents_with(&self, opts: &TextExtractOpts) -> String {
+        let inline_elements = default_inline_elements();
+        let mut builder = TextBuilder {
+            text: String::new(),
+            separator: &opts.block_separator,
+            pending_space: false,
+            pending_separator: false,
+        };
+        for edge in self.traverse_inclusive() {
+            let node = match edge {
+                NodeEdge::Start(node) => node,
+                NodeEdge::End(node) => {
+                    if is_html_block(&node, &inline_elements) {
+                        builder.pending_separator = true;
+                    }
+                    continue
+                }
+            };
+            if let Some(text) = node.as_text() {
+                builder.push(&text.borrow(), !opts.collapse_whitespace);
+            } else if let Some(element) = node.as_element() {
+                if is_html_block(&node, &inline_elements) {
+                    builder.pending_separator = true;
+                }
+                if opts.include_alt_text && has_alt_text(element) {
+                    let attributes = element.attributes.borrow();
+                    if let Some(alt) = attributes.get(atom!("alt")).or(attributes.get(atom!("title"))) {
+                        builder.push(alt, !opts.collapse_whitespace);
+                    }
+                }
+            }
+        }
+        builder.text
+    }
+
+
+    /// Return an approximation of the text of this subtree as a browser would render it,
+    /// like the DOM’s `innerText` as opposed to `text_contents`’s `textContent`.
+    ///
+    /// * Runs of whitespace are collapsed to a single space,
+    ///   except inside `<pre>`, `<textarea>`, `<listing>`, `<plaintext>`, and `<xmp>`
+    ///   where text is kept verbatim.
+    /// * Each block element (any element not in the set of HTML phrasing content elements
+    ///   used by default for pretty-printing) starts and ends a line,
+    ///   and `<br>` is a line break.
+    /// * Elements that are not rendered, like `<head>`, `<script>`, or `<style>`, are skipped.
+    /// * Collapsible whitespace is removed at the start and end of the result.
+    ///
+    /// CSS is not taken into account.
+    pub fn inner_text(&self) -> String {
+        let inline_elements = default_inline_elements();
+        let mut builder = TextBuilder {
+            text: String::new(),
+            separator: "\n",
+            pending_space: false,
+            pending_separator: false,
+        };
+        let mut hidden_depth = 0;
+        let mut verbatim_depth = 0;
+        for edge in self.traverse_inclusive() {
+            match edge {
+                NodeEdge::Start(node) => {
+                    if let Some(element) = node.as_element() {
+                        let name = &element.name.local;
+                        if element.name.ns != ns!(html) {
+                            continue
+                        }
+                        if hidden_depth > 0 || is_hidden(name) {
+                            hidden_depth += 1;
+                        } else if *name == atom!("br") {
+                            builder.line_break();
+                        } else {
+                            if !inline_elements.contains(name) {
+                                builder.pending_separator = true;
+                            }
+                            if is_verbatim(name) {
+                                verbatim_depth += 1;
+                            }
+                        }
+                    } else if let Some(text) = node.as_text() {
+                        if hidden_depth == 0 {
+                            builder.push(&text.borrow(), verbatim_depth > 0);
+                        }
+                    }
+                }
+                NodeEdge::End(node) => {
+                    if let Some(element) = node.as_element() {
+                        let name = &element.name.local;
+                        if element.name.ns != ns!(html) {
+                            continue
+                        }
+                        if hidden_depth > 0 {
+                            hidden_depth -= 1;
+                        } else {
+                            if !inline_elements.contains(name) {
+                                builder.pending_separator = true;
+                            }
+                            if is_verbatim(name) {
+                                verbatim_depth -= 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        builder.text
+    }
+}
+
+struct TextBuilder<'a> {
+    text: String,
+    /// Written at block boundaries.
+    separator: &'a str,
+    /// Whitespace was skipped since the last character written.
+    pending_space: bool,
+    /// A block boundary was crossed since the last character written.
+    pending_separator: bool,
+}
+
+impl<'a> TextBuilder<'a> {
+    fn push(&mut self, text: &str, verbatim: bool) {
+        for c in text.chars() {
+            if !verbatim && SELECTOR_WHITESPACE.contains(&c) {
+                self.pending_space = true;
+                continue
+            }
+            if !self.text.is_empty() {
+                if self.pending_separator {
+                    if !self.text.ends_with(self.separator) {
+                        self.text.push_str(self.separator)
+                    }
+                } else if self.pending_space && !self.text.ends_with(SELECTOR_WHITESPACE) {
+                    self.text.push(' ')
+                }
+            }
+            self.pending_space = false;
+            self.pending_separator = false;
+            self.text.push(c)
+        }
+    }
+
+    fn line_break(&mut self) {
+        self.pending_space = false;
+        self.pending_separator = false;
+        self.text.push('\n')
+    }
+}
+
+fn is_html_block(node: &NodeRef, inline_elements: &HashSet<Atom>) -> bool {
+    node.as_element().map_or(false, |element| {
+        element.name.ns == ns!(html) && !inline_elements.contains(&element.name.local)
+    })
+}
+
+fn has_alt_text(element: &ElementData) -> bool {
+    element.name.ns == ns!(html) && match element.name.local {
+        atom!("img") | atom!("area") => true,
+        atom!("input") => element.attributes.borrow().get(atom!("type"))
+            .map_or(false, |type_| type_.eq_ignore_ascii_case("image")),
+        _ => false,
+    }
+}
+
+fn is_hidden(local_name: &Atom) -> bool {
+    match *local_name {
+        atom!("head") | atom!("script") | atom!("style") | atom!("template") | atom!("noscript")
+        | atom!("noembed") | atom!("noframes") | atom!("iframe")
+            => true,
+        _ => false,
+    }
+}
+
+fn is_verbatim(local_name: &Atom) -> bool {
+    match *local_name {
+        atom!("pre") | atom!("textarea") | atom!("listing") | atom!("plaintext") | atom!("xmp")
+            => true,
+        _ => false,
+    }
+}
@@ -1,13 +1,17 @@
 use move_cell::MoveCell;
+use std::ascii::AsciiExt;
 use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt;
 use std::ops::Deref;
 use html5ever::tree_builder::QuirksMode;
 use rc::{Rc, Weak};
-use string_cache::QualName;
+use selectors::matching::SELECTOR_WHITESPACE;
+use string_cache::{Atom, Namespace, QualName};
 
-use attributes::Attributes;
-use iter::NodeIterator;
+use attributes::{Attributes, AttributesRef};
+use iter::{NodeIterator, NodeEdge};
+use node_data_ref::NodeDataRef;
 
 
 /// Node data specific to the node type.
@@ -33,6 +37,9 @@ pub enum NodeData {
 }
 
 /// Data specific to doctype nodes.
+///
+/// Doctype nodes can not be modified. To change a doctype, replace its node:
+/// `doctype_node.replace_with(NodeRef::new_doctype(name, public_id, system_id))`.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Doctype {
     /// The name of the doctype
@@ -56,14 +63,235 @@ pub struct ElementData {
 
     /// If the element is an HTML `<template>` element,
     /// the document fragment node that is the root of template contents.
+    ///
+    /// The parser puts the contents of `<template>` tags there rather than as children.
+    /// They are not descendants of the element, so tree traversals and `text_contents`
+    /// do not see them, but they are serialized inside the element’s tags.
     pub template_contents: Option<NodeRef>,
 }
 
+impl ElementData {
+    /// Borrow the attributes, for iterating `(local_name, value)` pairs
+    /// of attributes in the null namespace.
+    ///
+    /// ```rust
+    /// # let document = kuchiki::parse_html().one("<p class=a id=b>");
+    /// # use kuchiki::traits::*;
+    /// # let p = document.select_first("p").unwrap().unwrap();
+    /// for (name, value) in &p.attributes_iter() {
+    ///     println!("{}={}", name, value);
+    /// }
+    /// ```
+    #[inline]
+    pub fn attributes_iter<'a>(&'a self) -> AttributesRef<'a> {
+        AttributesRef(self.attributes.borrow())
+    }
+
+    /// Return the value of the attribute with the given local name in the null namespace,
+    /// if any.
+    #[inline]
+    pub fn get_attribute(&self, local_name: &str) -> Option<String> {
+        self.attributes.borrow().get(local_name).map(String::from)
+    }
+
+    /// Return whether there is an attribute with the given local name in the null namespace,
+    /// like `get_attribute`.
+    ///
+    /// Attributes in other namespaces (such as `xlink:href`) are never matched.
+    #[inline]
+    pub fn has_attribute(&self, local_name: &str) -> bool {
+        self.attributes.borrow().contains(local_name)
+    }
+
+    /// Return the number of attributes of this element, in any namespace.
+    #[inline]
+    pub fn attribute_count(&self) -> usize {
+        self.attributes.borrow().list.len()
+    }
+
+    /// Set the value of the attribute with the given local name in the null namespace,
+    /// adding it if it is not already present.
+    #[inline]
+    pub fn set_attribute(&self, local_name: &str, value: &str) {
+        self.attributes.borrow_mut().insert(local_name, value.to_owned());
+    }
+
+    /// Set the values of the given attributes, in order.
+    ///
+    /// Like `set_attribute`, existing attributes keep their position
+    /// and new ones are added at the end.
+    #[inline]
+    pub fn set_attributes<I>(&self, attributes: I) where I: IntoIterator<Item=(QualName, String)> {
+        self.attributes.borrow_mut().extend(attributes)
+    }
+
+    /// Set the values of the given attributes in the null namespace, in order.
+    #[inline]
+    pub fn set_attributes_str<'a, I>(&self, attributes: I)
+                                     where I: IntoIterator<Item=(&'a str, &'a str)> {
+        self.set_attributes(attributes.into_iter().map(|(local_name, value)| {
+            (QualName::new(ns!(), Atom::from(local_name)), value.to_owned())
+        }))
+    }
+
+    /// Replace the value of the attribute with the given local name in the null namespace
+    /// with the result of calling `f` with the current value.
+    ///
+    /// Does nothing if there is no such attribute.
+    /// The attributes are not borrowed while `f` runs, so it can access them.
+    pub fn map_attribute<F>(&self, local_name: &str, f: F) where F: FnOnce(&str) -> String {
+        let value = match self.get_attribute(local_name) {
+            Some(value) => f(&value),
+            None => return
+        };
+        self.attributes.borrow_mut().insert(local_name, value);
+    }
+
+    /// Call `f` with the name and value of each attribute, in order,
+    /// and replace the value with the result, or remove the attribute if it returns `None`.
+    ///
+    /// All new values are computed before any is changed,
+    /// so `f` always sees the original attributes.
+    pub fn map_all_attributes<F>(&self, mut f: F) where F: FnMut(&QualName, &str) -> Option<String> {
+        let list = self.attributes.borrow().list.clone();
+        let list = list.into_iter().filter_map(|(name, value)| {
+            f(&name, &value).map(|value| (name, value))
+        }).collect();
+        self.attributes.borrow_mut().list = list;
+    }
+
+    /// Remove the attribute with the given local name in the null namespace, if any.
+    #[inline]
+    pub fn remove_attribute(&self, local_name: &str) {
+        self.attributes.borrow_mut().remove(local_name);
+    }
+
+    /// Return the value of the first attribute in the null namespace
+    /// whose local name matches the given name ignoring ASCII case, if any.
+    ///
+    /// Attributes in other namespaces (such as `xlink:href`) are never matched.
+    pub fn get_attribute_ignore_case(&self, name: &str) -> Option<String> {
+        self.attributes.borrow().list.iter()
+            .find(|&&(ref key, _)| key.ns == ns!() && (&*key.local).eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.clone())
+    }
+
+    /// Return whether there is an attribute in the null namespace
+    /// whose local name matches the given name ignoring ASCII case.
+    ///
+    /// Attributes in other namespaces (such as `xlink:href`) are never matched.
+    pub fn has_attribute_ignore_case(&self, name: &str) -> bool {
+        self.attributes.borrow().list.iter()
+            .any(|&(ref key, _)| key.ns == ns!() && (&*key.local).eq_ignore_ascii_case(name))
+    }
+
+    /// Return the `data-*` attributes in the null namespace,
+    /// keyed by their name without the `data-` prefix (`data-user-id` gives `user-id`).
+    pub fn dataset(&self) -> HashMap<String, String> {
+        self.attributes.borrow().list.iter().filter_map(|&(ref name, ref value)| {
+            if name.ns == ns!() && name.local.starts_with("data-") {
+                Some((name.local["data-".len()..].to_owned(), value.clone()))
+            } else {
+                None
+            }
+        }).collect()
+    }
+
+    /// Return the `data-*` attributes in the null namespace,
+    /// keyed by camel-cased names like `HTMLElement.dataset` in the DOM
+    /// (`data-user-id` gives `userId`).
+    pub fn dataset_camel_case(&self) -> HashMap<String, String> {
+        self.dataset().into_iter().map(|(name, value)| {
+            let mut camel_case = String::with_capacity(name.len());
+            let mut chars = name.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (c, chars.peek()) {
+                    ('-', Some(&next)) if next >= 'a' && next <= 'z' => {
+                        camel_case.push(next.to_ascii_uppercase());
+                        chars.next();
+                    }
+                    _ => camel_case.push(c)
+                }
+            }
+            (camel_case, value)
+        }).collect()
+    }
+
+    /// Return whether the `class` attribute contains the given class name.
+    ///
+    /// This is exact token matching: `btn` is not found in `class="btn-primary"`.
+    pub fn has_class(&self, name: &str) -> bool {
+        !name.is_empty() &&
+        match self.attributes.borrow().get(atom!("class")) {
+            Some(class_attr) => class_attr.split(SELECTOR_WHITESPACE).any(|class| class == name),
+            None => false,
+        }
+    }
+
+    /// Add a class name to the `class` attribute, unless it is already there.
+    ///
+    /// The attribute is rewritten with classes separated by a single space
+    /// and duplicates removed.
+    pub fn add_class(&self, name: &str) {
+        if name.is_empty() {
+            return
+        }
+        let mut attributes = self.attributes.borrow_mut();
+        let new_value = {
+            let mut classes = class_list(attributes.get(atom!("class")).unwrap_or(""));
+            if !classes.contains(&name) {
+                classes.push(name)
+            }
+            classes.join(" ")
+        };
+        attributes.insert(atom!("class"), new_value);
+    }
+
+    /// Remove a class name from the `class` attribute, if it is there.
+    ///
+    /// The attribute is rewritten with classes separated by a single space
+    /// and duplicates removed.
+    /// When the last class is removed, the attribute is left present but empty,
+    /// like with `Element.classList` in the DOM.
+    pub fn remove_class(&self, name: &str) {
+        let mut attributes = self.attributes.borrow_mut();
+        let new_value = match attributes.get(atom!("class")) {
+            Some(class_attr) => {
+                let mut classes = class_list(class_attr);
+                classes.retain(|&class| class != name);
+                classes.join(" ")
+            }
+            None => return
+        };
+        attributes.insert(atom!("class"), new_value);
+    }
+}
+
+/// Split a `class` attribute into its unique, non-empty tokens.
+fn class_list(class_attr: &str) -> Vec<&str> {
+    let mut classes = Vec::new();
+    for class in class_attr.split(SELECTOR_WHITESPACE) {
+        if !class.is_empty() && !classes.contains(&class) {
+            classes.push(class)
+        }
+    }
+    classes
+}
+
+fn has_ascii_uppercase(name: &str) -> bool {
+    name.bytes().any(|byte| matches!(byte, b'A'...b'Z'))
+}
+
 /// Data specific to document nodes.
-#[derive(Debug, PartialEq, Clone)]
 pub struct DocumentData {
     #[doc(hidden)]
     pub _quirks_mode: Cell<QuirksMode>,
+
+    #[doc(hidden)]
+    pub _id_index: RefCell<Option<HashMap<String, Weak<Node>>>>,
+
+    #[doc(hidden)]
+    pub _source_positions: RefCell<Option<HashMap<usize, (Weak<Node>, (usize, usize))>>>,
 }
 
 impl DocumentData {
@@ -72,6 +300,49 @@ impl DocumentData {
     pub fn quirks_mode(&self) -> QuirksMode {
         self._quirks_mode.get()
     }
+
+    /// Override the quirks mode of the document.
+    ///
+    /// Documents created with `NodeRef::new_document` start in no-quirks mode.
+    /// Kuchiki itself does not use the quirks mode:
+    /// it does not affect serialization or selector matching.
+    #[inline]
+    pub fn set_quirks_mode(&self, mode: QuirksMode) {
+        self._quirks_mode.set(mode)
+    }
+
+    /// Discard the index used by `NodeRef::get_element_by_id`.
+    ///
+    /// It will be rebuilt from the current state of the tree on the next lookup.
+    #[inline]
+    pub fn invalidate_id_cache(&self) {
+        *self._id_index.borrow_mut() = None
+    }
+}
+
+impl fmt::Debug for DocumentData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "DocumentData {{ quirks_mode: {:?} }}", self.quirks_mode())
+    }
+}
+
+/// The id index is a cache and source positions are metadata, they are not compared.
+impl PartialEq for DocumentData {
+    fn eq(&self, other: &DocumentData) -> bool {
+        self.quirks_mode() == other.quirks_mode()
+    }
+}
+
+/// The id index and source positions are not copied,
+/// since they refer to nodes in the original tree.
+impl Clone for DocumentData {
+    fn clone(&self) -> DocumentData {
+        DocumentData {
+            _quirks_mode: Cell::new(self.quirks_mode()),
+            _id_index: RefCell::new(None),
+            _source_positions: RefCell::new(None),
+        }
+    }
 }
 
 /// A strong reference to a node.
@@ -90,6 +361,10 @@ impl DocumentData {
 /// To avoid detroying nodes prematurely,
 /// programs typically hold a strong reference to the root of a document
 /// until they’re done with that document.
+///
+/// **Note:** `NodeRef::clone` does not copy the node,
+/// it returns another reference to the same node.
+/// Use `shallow_clone` or `clone_subtree` to make a copy.
 #[derive(Clone, Debug)]
 pub struct NodeRef(pub Rc<Node>);
 
@@ -102,6 +377,8 @@ impl Deref for NodeRef {
 }
 
 impl Eq for NodeRef {}
+/// Nodes are compared by identity, like with `is_same_node`.
+/// Use `is_equal_node` or `deep_eq` to compare their contents.
 impl PartialEq for NodeRef {
     #[inline]
     fn eq(&self, other: &NodeRef) -> bool {
@@ -215,9 +492,29 @@ impl NodeRef {
                 None
             },
             name: name,
-            attributes: RefCell::new(Attributes {
-                map: attributes.into_iter().collect()
-            }),
+            attributes: RefCell::new(attributes.into_iter().collect()),
+        }))
+    }
+
+    /// Create a new element in the HTML namespace with the given local name, such as `"div"`,
+    /// and no attributes.
+    ///
+    /// Like the HTML parser does for tag names, the name is converted to ASCII lowercase.
+    #[inline]
+    pub fn new_element_str(local_name: &str) -> NodeRef {
+        NodeRef::new_element_str_with_attributes(local_name, None)
+    }
+
+    /// Create a new element in the HTML namespace with the given local name
+    /// and attributes in the null namespace, such as `[("class", "foo")]`.
+    ///
+    /// Like the HTML parser does, the names are converted to ASCII lowercase.
+    /// If an attribute is repeated, the last value is used.
+    pub fn new_element_str_with_attributes<'a, I>(local_name: &str, attributes: I) -> NodeRef
+                                                  where I: IntoIterator<Item=(&'a str, &'a str)> {
+        let name = QualName::new(ns!(html), Atom::from(&*local_name.to_ascii_lowercase()));
+        NodeRef::new_element(name, attributes.into_iter().map(|(name, value)| {
+            (QualName::new(ns!(), Atom::from(&*name.to_ascii_lowercase())), value.to_owned())
         }))
     }
 
@@ -249,16 +546,270 @@ impl NodeRef {
     pub fn new_document() -> NodeRef {
         NodeRef::new(NodeData::Document(DocumentData {
             _quirks_mode: Cell::new(QuirksMode::NoQuirks),
+            _id_index: RefCell::new(None),
+            _source_positions: RefCell::new(None),
         }))
     }
 
-    /// Return the concatenation of all text nodes in this subtree.
-    pub fn text_contents(&self) -> String {
-        let mut s = String::new();
+    /// Create a new document node with the given children, appended in order.
+    ///
+    /// For example, `NodeRef::new_document_with(vec![NodeRef::new_doctype("html", "", ""),
+    /// html_element])`.
+    pub fn new_document_with<I>(children: I) -> NodeRef where I: IntoIterator<Item=NodeRef> {
+        let document = NodeRef::new_document();
+        for child in children {
+            document.append(child)
+        }
+        document
+    }
+
+    /// Return a copy of this node without its children, as a new detached node.
+    ///
+    /// Attributes and text are copied, as well as template contents
+    /// (which are not children). The copy does not share any data with the original.
+    #[inline]
+    pub fn shallow_clone(&self) -> NodeRef {
+        NodeRef::new(self.data.clone_detached())
+    }
+
+    /// Return a deep copy of this node and its descendants, in a new detached tree.
+    ///
+    /// The copy does not share any data with the original:
+    /// mutating one tree never affects the other.
+    pub fn clone_subtree(&self) -> NodeRef {
+        // Use an explicit stack of ancestors rather than recursion,
+        // for the same reason as in `impl Drop for Node`.
+        let mut stack: Vec<NodeRef> = Vec::new();
+        let mut root = None;
+        for edge in self.traverse_inclusive() {
+            match edge {
+                NodeEdge::Start(node) => {
+                    let copy = NodeRef::new(node.data.clone_detached());
+                    match stack.last() {
+                        Some(parent) => parent.append(copy.clone()),
+                        None => root = Some(copy.clone()),
+                    }
+                    stack.push(copy);
+                }
+                NodeEdge::End(_) => {
+                    stack.pop();
+                }
+            }
+        }
+        root.unwrap()
+    }
+
+    /// Return the root of the tree that this node is part of:
+    /// its furthest ancestor, or this node itself if it has no parent.
+    #[inline]
+    pub fn root(&self) -> NodeRef {
+        let mut root = self.clone();
+        while let Some(parent) = root.parent() {
+            root = parent
+        }
+        root
+    }
+
+    /// Return the number of ancestors of this node: 0 for the root of a tree.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.ancestors().count()
+    }
+
+    /// Return the number of steps from the given ancestor to this node,
+    /// 0 if it is this node itself, or `None` if it is not an inclusive ancestor.
+    #[inline]
+    pub fn depth_from(&self, ancestor: &NodeRef) -> Option<usize> {
+        self.inclusive_ancestors().position(|node| node == *ancestor)
+    }
+
+    /// If the root of the tree that this node is part of is a document,
+    /// return a strong reference to its document-specific data.
+    ///
+    /// This is `None` for nodes in detached subtrees.
+    #[inline]
+    pub fn document(&self) -> Option<NodeDataRef<DocumentData>> {
+        self.root().into_document_ref()
+    }
+
+    /// Return the number of strong references to this node,
+    /// including `NodeRef`s, `NodeDataRef`s, and links from its parent and previous sibling.
+    ///
+    /// This is a diagnostic for finding leaks, for example in tests
+    /// that check that a removed subtree is not kept alive.
+    /// Which links are strong is an implementation detail that may change.
+    #[inline]
+    pub fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    /// Return the number of weak references to this node,
+    /// including links from its children, its next sibling, its parent if it is the last child,
+    /// and index tables.
+    ///
+    /// Like `strong_count`, this is only a diagnostic.
+    #[inline]
+    pub fn weak_count(&self) -> usize {
+        Rc::weak_count(&self.0)
+    }
+
+    /// Return the first descendant element, in tree order, with the given `id` attribute.
+    ///
+    /// On a document node, the first call builds an index of all `id` attributes
+    /// and later calls are constant-time lookups.
+    /// The index reflects the tree at the time it was built:
+    /// call `DocumentData::invalidate_id_cache` after changing elements or their `id`.
+    /// On other nodes, this searches the subtree every time.
+    pub fn get_element_by_id(&self, id: &str) -> Option<NodeDataRef<ElementData>> {
+        if id.is_empty() {
+            return None
+        }
+        let document = match self.as_document() {
+            Some(document) => document,
+            None => {
+                return self.descendants().elements().find(|element| {
+                    element.attributes.borrow().get(atom!("id")) == Some(id)
+                })
+            }
+        };
+        let mut index = document._id_index.borrow_mut();
+        if index.is_none() {
+            let mut new_index = HashMap::new();
+            for element in self.descendants().elements() {
+                if let Some(element_id) = element.attributes.borrow().get(atom!("id")) {
+                    // Keep the first element in tree order for duplicate ids.
+                    new_index.entry(element_id.to_owned())
+                             .or_insert_with(|| Rc::downgrade(&element.as_node().0));
+                }
+            }
+            *index = Some(new_index);
+        }
+        index.as_ref().unwrap().get(id)
+             .and_then(|weak| weak.upgrade())
+             .and_then(|rc| NodeRef(rc).into_element_ref())
+    }
+
+    /// Return the descendant elements with the given local name, in tree order,
+    /// like `getElementsByTagName` in the DOM.
+    ///
+    /// Matching is ASCII case-insensitive for elements in the HTML namespace,
+    /// and case-sensitive for others. The name `*` matches every element.
+    pub fn get_elements_by_tag_name(&self, name: &str) -> Vec<NodeDataRef<ElementData>> {
+        let lower_name = name.to_ascii_lowercase();
+        self.descendants().elements().filter(|element| {
+            name == "*" ||
+            if element.name.ns == ns!(html) {
+                *element.name.local == *lower_name
+            } else {
+                *element.name.local == *name
+            }
+        }).collect()
+    }
+
+    /// Merge adjacent text nodes in this subtree and remove empty text nodes,
+    /// like `Node.normalize()` in the DOM.
+    ///
+    /// Other nodes (including comments between text nodes) are not affected.
+    pub fn normalize(&self) {
+        // Collect first, since detaching nodes during a traversal would disturb it.
+        let text_nodes = self.descendants().text_nodes().collect::<Vec<_>>();
+        for text_node in text_nodes {
+            let node = text_node.as_node();
+            if node.parent().is_none() {
+                // Already merged into a previous text node.
+                continue
+            }
+            let mut text = text_node.borrow_mut();
+            while let Some(next) = node.next_sibling() {
+                match next.as_text() {
+                    Some(next_text) => text.push_str(&next_text.borrow()),
+                    None => break
+                }
+                next.detach()
+            }
+            if text.is_empty() {
+                node.detach()
+            }
+        }
+    }
+
+    /// Replace every occurrence of `from` with `to` in the text nodes of this subtree,
+    /// and return the number of text nodes that were changed.
+    ///
+    /// Element names, attributes, and comments are not affected.
+    pub fn replace_text(&self, from: &str, to: &str) -> usize {
+        if from.is_empty() {
+            return 0
+        }
+        self.replace_text_with(|text| text.replace(from, to))
+    }
+
+    /// Replace the contents of each text node in this subtree
+    /// with the result of calling `f` on it,
+    /// and return the number of text nodes whose contents changed.
+    pub fn replace_text_with<F>(&self, mut f: F) -> usize where F: FnMut(&str) -> String {
+        let mut count = 0;
         for text_node in self.inclusive_descendants().text_nodes() {
-            s.push_str(&text_node.borrow());
+            let new_text = f(&text_node.borrow());
+            let mut text = text_node.borrow_mut();
+            if *text != new_text {
+                *text = new_text;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Split this text node at the given byte offset.
+    ///
+    /// This node keeps the text before the offset,
+    /// and a new text node with the rest is inserted as its next sibling and returned.
+    /// Either of them may be empty if the offset is at the start or end of the text.
+    ///
+    /// Panics if this is not a text node,
+    /// or if the offset is not on a `char` boundary or is past the end of the text.
+    pub fn split_text(&self, offset: usize) -> NodeRef {
+        let text = self.as_text().expect("split_text() called on a non-text node");
+        let suffix = {
+            let mut text = text.borrow_mut();
+            // Slicing panics unless `offset` is a valid char boundary.
+            let suffix = text[offset..].to_owned();
+            text.truncate(offset);
+            suffix
+        };
+        let new_node = NodeRef::new_text(suffix);
+        self.insert_after(new_node.clone());
+        new_node
+    }
+}
+
+impl NodeData {
+    /// Like `clone()`, but also deep-copies template contents
+    /// so that the result shares no node with `self`.
+    fn clone_detached(&self) -> NodeData {
+        match *self {
+            NodeData::Element(ref element) => NodeData::Element(ElementData {
+                name: element.name.clone(),
+                attributes: element.attributes.clone(),
+                template_contents: element.template_contents.as_ref().map(NodeRef::clone_subtree),
+            }),
+            ref other => other.clone(),
+        }
+    }
+
+    /// Like `==`, but compares template contents structurally rather than by identity.
+    fn shallow_eq(&self, other: &NodeData) -> bool {
+        match (self, other) {
+            (&NodeData::Element(ref a), &NodeData::Element(ref b)) => {
+                a.name == b.name && a.attributes == b.attributes &&
+                match (&a.template_contents, &b.template_contents) {
+                    (&Some(ref a), &Some(ref b)) => a.deep_eq(b),
+                    (&None, &None) => true,
+                    _ => false,
+                }
+            }
+            (a, b) => a == b,
         }
-        s
     }
 }
 
@@ -269,6 +820,249 @@ impl Node {
         &self.data
     }
 
+    /// Return the concatenation of all text nodes in this subtree, in tree order.
+    ///
+    /// Comments, doctypes, and tags themselves are skipped.
+    /// Like in the DOM, template contents are not included since they are not descendants.
+    pub fn text_contents(&self) -> String {
+        let mut s = String::new();
+        if let Some(text) = self.as_text() {
+            s.push_str(&text.borrow());
+        }
+        let mut next_child = self.first_child();
+        while let Some(child) = next_child {
+            for text_node in child.inclusive_descendants().text_nodes() {
+                s.push_str(&text_node.borrow());
+            }
+            next_child = child.next_sibling();
+        }
+        s
+    }
+
+    /// Return the text of the text node siblings before this node,
+    /// up to the previous element sibling or the first child of the parent,
+    /// concatenated in tree order with leading and trailing whitespace removed.
+    ///
+    /// Comments and other non-element siblings are skipped.
+    pub fn preceding_text(&self) -> String {
+        let mut texts = Vec::new();
+        let mut next = self.previous_sibling();
+        while let Some(sibling) = next {
+            if sibling.as_element().is_some() {
+                break
+            }
+            if let Some(text) = sibling.as_text() {
+                texts.push(text.borrow().clone());
+            }
+            next = sibling.previous_sibling();
+        }
+        texts.reverse();
+        texts.concat().trim_matches(SELECTOR_WHITESPACE).to_owned()
+    }
+
+    /// Return the text of the text node siblings after this node,
+    /// up to the next element sibling or the last child of the parent,
+    /// concatenated with leading and trailing whitespace removed.
+    ///
+    /// For example, the following text of the first `<b>` in
+    /// `<b>Prep:</b> 10 min <b>Cook:</b> 1 h` is `"10 min"`.
+    /// Comments and other non-element siblings are skipped.
+    pub fn following_text(&self) -> String {
+        let mut text = String::new();
+        let mut next = self.next_sibling();
+        while let Some(sibling) = next {
+            if sibling.as_element().is_some() {
+                break
+            }
+            if let Some(sibling_text) = sibling.as_text() {
+                text.push_str(&sibling_text.borrow());
+            }
+            next = sibling.next_sibling();
+        }
+        text.trim_matches(SELECTOR_WHITESPACE).to_owned()
+    }
+
+    /// Return whether this node has at least one child.
+    #[inline]
+    pub fn has_children(&self) -> bool {
+        !self.first_child.is_none()
+    }
+
+    /// Return whether this subtree only contains whitespace text,
+    /// for example an element with no children or with only a line break as its text.
+    ///
+    /// This node itself can be of any type, but text nodes must contain only whitespace
+    /// (including non-ASCII whitespace like U+00A0) and other nodes must have no children.
+    /// Any other descendant, such as an element or a comment, makes this return `false`.
+    pub fn is_whitespace_only(&self) -> bool {
+        if let Some(text) = self.as_text() {
+            if !text.borrow().chars().all(char::is_whitespace) {
+                return false
+            }
+        }
+        let mut next_child = self.first_child();
+        while let Some(child) = next_child {
+            let all_whitespace = child.inclusive_descendants().all(|node| match node.as_text() {
+                Some(text) => text.borrow().chars().all(char::is_whitespace),
+                None => false,
+            });
+            if !all_whitespace {
+                return false
+            }
+            next_child = child.next_sibling();
+        }
+        true
+    }
+
+    /// Return the zero-based position of this node among its parent’s children,
+    /// or `None` if it has no parent.
+    pub fn index_in_parent(&self) -> Option<usize> {
+        if self.parent.is_none() {
+            return None
+        }
+        let mut index = 0;
+        let mut previous = self.previous_sibling();
+        while let Some(node) = previous {
+            index += 1;
+            previous = node.previous_sibling();
+        }
+        Some(index)
+    }
+
+    /// Return the zero-based position of this element among its parent’s element children,
+    /// or `None` if it has no parent or is not an element.
+    ///
+    /// This is one less than the `n` of a matching CSS `:nth-child(n)` selector.
+    pub fn element_index_in_parent(&self) -> Option<usize> {
+        if self.parent.is_none() || self.as_element().is_none() {
+            return None
+        }
+        let mut index = 0;
+        let mut previous = self.previous_sibling();
+        while let Some(node) = previous {
+            if node.as_element().is_some() {
+                index += 1;
+            }
+            previous = node.previous_sibling();
+        }
+        Some(index)
+    }
+
+    /// Return whether the other node is this node or one of its descendants,
+    /// like `Node.contains` in the DOM.
+    ///
+    /// This walks up from the other node, so it takes time proportional to its depth.
+    pub fn contains(&self, other: &NodeRef) -> bool {
+        other.inclusive_ancestors().any(|node| &*node.0 as *const Node == self as *const Node)
+    }
+
+    /// Return the number of nodes in this subtree, not including this node.
+    pub fn descendant_count(&self) -> usize {
+        let mut count = 0;
+        let mut next_child = self.first_child();
+        while let Some(child) = next_child {
+            count += child.inclusive_descendants().count();
+            next_child = child.next_sibling();
+        }
+        count
+    }
+
+    /// Return the number of element nodes in this subtree, not including this node.
+    pub fn element_count(&self) -> usize {
+        let mut count = 0;
+        let mut next_child = self.first_child();
+        while let Some(child) = next_child {
+            count += child.inclusive_descendants().elements().count();
+            next_child = child.next_sibling();
+        }
+        count
+    }
+
+    /// Return the line and column (both starting at 1) of the `<` of the start tag
+    /// of this element in the source, if the document was parsed with
+    /// `parse_html_with_source_positions`.
+    ///
+    /// Elements implied by the parser (like a missing `<body>`) get the position
+    /// of the start tag that caused them to be created, or of the last start tag before
+    /// the markup that did.
+    /// The position is found by looking for `<` followed by an ASCII letter,
+    /// so such text in attribute values or comments may shift it.
+    /// Returns `None` for other nodes, for nodes not in such a document
+    /// (including template contents), and for nodes created after parsing.
+    pub fn source_position(&self) -> Option<(usize, usize)> {
+        let mut root = match self.parent() {
+            Some(parent) => parent,
+            None => return None
+        };
+        while let Some(parent) = root.parent() {
+            root = parent
+        }
+        let document = match root.as_document() {
+            Some(document) => document,
+            None => return None
+        };
+        let positions = document._source_positions.borrow();
+        let key = self as *const Node as usize;
+        match positions.as_ref().and_then(|positions| positions.get(&key)) {
+            // The address of a dropped node may have been reused.
+            Some(&(ref weak, position)) => match weak.upgrade() {
+                Some(ref rc) if &**rc as *const Node as usize == key => Some(position),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    /// Return whether this subtree and the other one have the same structure and contents.
+    ///
+    /// Node types, element names, attributes, text and comment contents are compared,
+    /// and children are compared in order. Attribute order is not significant.
+    /// Unlike `==` on `NodeRef`, this does not compare node identity.
+    pub fn deep_eq(&self, other: &Node) -> bool {
+        if !self.data.shallow_eq(&other.data) {
+            return false
+        }
+        // Pairs of nodes to compare together with their following siblings and descendants.
+        // An explicit stack avoids recursion, as in `impl Drop for Node`.
+        let mut stack = vec![(self.first_child(), other.first_child())];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (Some(a), Some(b)) => {
+                    if !a.data.shallow_eq(&b.data) {
+                        return false
+                    }
+                    stack.push((a.next_sibling(), b.next_sibling()));
+                    stack.push((a.first_child(), b.first_child()));
+                }
+                (None, None) => {}
+                _ => return false
+            }
+        }
+        true
+    }
+
+    /// Return whether this is the same node as the other one, like the DOM’s `isSameNode`.
+    ///
+    /// This is what `==` on `NodeRef` compares.
+    #[inline]
+    pub fn is_same_node(&self, other: &Node) -> bool {
+        self as *const Node == other as *const Node
+    }
+
+    /// Return whether this node and the other one are equal,
+    /// not taking their children into account.
+    ///
+    /// This compares node types, element names and attributes (in any order),
+    /// text and comment contents, doctype names and identifiers,
+    /// and document quirks modes.
+    /// Template contents are compared structurally, like in `deep_eq`.
+    ///
+    /// Use `deep_eq` to also compare descendants, like the DOM’s `isEqualNode`.
+    #[inline]
+    pub fn is_equal_node(&self, other: &Node) -> bool {
+        self.data.shallow_eq(&other.data)
+    }
+
     /// If this node is an element, return a reference to element-specific data.
     #[inline]
     pub fn as_element(&self) -> Option<&ElementData> {
@@ -278,6 +1072,18 @@ impl Node {
         }
     }
 
+    /// If this node is an element, return its local name, such as `"div"`.
+    #[inline]
+    pub fn tag_name(&self) -> Option<&str> {
+        self.as_element().map(|element| &*element.name.local)
+    }
+
+    /// If this node is an element, return its namespace.
+    #[inline]
+    pub fn namespace(&self) -> Option<&Namespace> {
+        self.as_element().map(|element| &element.name.ns)
+    }
+
     /// If this node is a text node, return a reference to its contents.
     #[inline]
     pub fn as_text(&self) -> Option<&RefCell<String>> {
@@ -314,6 +1120,47 @@ impl Node {
         }
     }
 
+    /// Return whether this node is an element.
+    #[inline]
+    pub fn is_element(&self) -> bool {
+        matches!(self.data, NodeData::Element(_))
+    }
+
+    /// Return whether this node is a text node.
+    #[inline]
+    pub fn is_text(&self) -> bool {
+        matches!(self.data, NodeData::Text(_))
+    }
+
+    /// Return whether this node is a comment.
+    #[inline]
+    pub fn is_comment(&self) -> bool {
+        matches!(self.data, NodeData::Comment(_))
+    }
+
+    /// Return whether this node is a doctype.
+    #[inline]
+    pub fn is_doctype(&self) -> bool {
+        matches!(self.data, NodeData::Doctype(_))
+    }
+
+    /// Return whether this node is a document.
+    #[inline]
+    pub fn is_document(&self) -> bool {
+        matches!(self.data, NodeData::Document(_))
+    }
+
+    /// Return whether this node is an HTML element with the given local name,
+    /// compared ignoring ASCII case, such as `is_element_named("div")`.
+    ///
+    /// Elements in other namespaces, like SVG, never match.
+    #[inline]
+    pub fn is_element_named(&self, local_name: &str) -> bool {
+        self.as_element().map_or(false, |element| {
+            element.name.ns == ns!(html) && (&*element.name.local).eq_ignore_ascii_case(local_name)
+        })
+    }
+
     /// Return a reference to the parent node, unless this node is the root of the tree.
     #[inline]
     pub fn parent(&self) -> Option<NodeRef> {
@@ -344,8 +1191,37 @@ impl Node {
         self.next_sibling.clone_inner().map(NodeRef)
     }
 
+    /// Return the first child of this node that is an element, if any.
+    ///
+    /// Text, comment, and other non-element children are skipped.
+    pub fn first_element_child(&self) -> Option<NodeDataRef<ElementData>> {
+        let mut next = self.first_child();
+        while let Some(child) = next {
+            next = child.next_sibling();
+            if let Some(element) = child.into_element_ref() {
+                return Some(element)
+            }
+        }
+        None
+    }
+
+    /// Return the last child of this node that is an element, if any.
+    ///
+    /// Text, comment, and other non-element children are skipped.
+    pub fn last_element_child(&self) -> Option<NodeDataRef<ElementData>> {
+        let mut next = self.last_child();
+        while let Some(child) = next {
+            next = child.previous_sibling();
+            if let Some(element) = child.into_element_ref() {
+                return Some(element)
+            }
+        }
+        None
+    }
+
     /// Detach a node from its parent and siblings. Children are not affected.
     ///
+    /// The node keeps its descendants, so that the whole subtree can be inserted elsewhere.
     /// To remove a node and its descendants, detach it and drop any strong reference to it.
     pub fn detach(&self) {
         let parent_weak = self.parent.take();
@@ -391,6 +1267,50 @@ impl NodeRef {
         self.first_child.set(Some(new_child.0));
     }
 
+    /// Append new children to this node, after existing children, in iteration order.
+    ///
+    /// Each new child is detached from its previous position.
+    pub fn append_children<I>(&self, new_children: I) where I: IntoIterator<Item=NodeRef> {
+        // `append` finds the current last child through `self.last_child`,
+        // so this does not re-walk the list of children.
+        for new_child in new_children {
+            self.append(new_child)
+        }
+    }
+
+    /// Create a new text node, append it to this node after existing children,
+    /// and return it.
+    #[inline]
+    pub fn append_text<T: Into<String>>(&self, text: T) -> NodeRef {
+        let node = NodeRef::new_text(text);
+        self.append(node.clone());
+        node
+    }
+
+    /// Append text after this node’s existing children,
+    /// adding it to the last child if that is a text node
+    /// or otherwise creating a new text node, and return that text node.
+    ///
+    /// This keeps the children normalized, as with `normalize`.
+    pub fn append_text_merged<T: Into<String>>(&self, text: T) -> NodeRef {
+        if let Some(last_child) = self.last_child() {
+            if let Some(existing) = last_child.as_text() {
+                existing.borrow_mut().push_str(&text.into());
+                return last_child.clone()
+            }
+        }
+        self.append_text(text)
+    }
+
+    /// Create a new comment node, append it to this node after existing children,
+    /// and return it.
+    #[inline]
+    pub fn append_comment<T: Into<String>>(&self, text: T) -> NodeRef {
+        let node = NodeRef::new_comment(text);
+        self.append(node.clone());
+        node
+    }
+
     /// Prepend a new child to this node, before existing children.
     ///
     /// The new child is detached from its previous position.
@@ -447,4 +1367,281 @@ impl NodeRef {
             parent.first_child.set(Some(new_sibling.0));
         }
     }
+
+    /// Insert new siblings before this node, as a contiguous block in iteration order.
+    ///
+    /// Each new sibling is detached from its previous position.
+    /// This node itself is skipped if it is part of the iterator.
+    pub fn insert_children_before<I>(&self, new_siblings: I)
+                                     where I: IntoIterator<Item=NodeRef> {
+        for new_sibling in new_siblings {
+            if new_sibling != *self {
+                self.insert_before(new_sibling)
+            }
+        }
+    }
+
+    /// Insert new siblings after this node, as a contiguous block in iteration order.
+    ///
+    /// Each new sibling is detached from its previous position.
+    /// This node itself is skipped if it is part of the iterator.
+    pub fn insert_children_after<I>(&self, new_siblings: I)
+                                    where I: IntoIterator<Item=NodeRef> {
+        let mut previous = self.clone();
+        for new_sibling in new_siblings {
+            if new_sibling != *self {
+                previous.insert_after(new_sibling.clone());
+                previous = new_sibling;
+            }
+        }
+    }
+
+    /// Detach this node from its parent and siblings, and return a new reference to it.
+    ///
+    /// Like with `detach`, the node keeps its descendants:
+    /// the result is the root of the removed subtree, ready to be inserted elsewhere.
+    #[inline]
+    pub fn make_orphan(&self) -> NodeRef {
+        self.detach();
+        self.clone()
+    }
+
+    /// Detach all children of this node.
+    ///
+    /// Children (and their descendants) that are not referenced elsewhere are dropped.
+    pub fn remove_children(&self) {
+        while let Some(child) = self.first_child() {
+            child.detach()
+        }
+    }
+
+    /// Move all children of this node, in order, after the existing children of `destination`.
+    ///
+    /// The list of children is moved as a whole rather than one node at a time:
+    /// only the parent link of each child is updated.
+    ///
+    /// Panics if `destination` is a descendant of this node.
+    pub fn move_children_to(&self, destination: &NodeRef) {
+        let (first, last) = match self.take_children_for(destination) {
+            Some(children) => children,
+            None => return
+        };
+        let last_weak = Rc::downgrade(&last.0);
+        match destination.last_child.replace(Some(last_weak)).and_then(|weak| weak.upgrade()) {
+            Some(previous_last) => {
+                first.previous_sibling.set(Some(Rc::downgrade(&previous_last)));
+                debug_assert!(previous_last.next_sibling.is_none());
+                previous_last.next_sibling.set(Some(first.0));
+            }
+            None => {
+                debug_assert!(destination.first_child.is_none());
+                destination.first_child.set(Some(first.0))
+            }
+        }
+    }
+
+    /// Append a deep copy of each child of this node, in order,
+    /// after the existing children of `destination`. This node is not changed.
+    ///
+    /// The copies are made with `clone_subtree` before any is appended,
+    /// so `destination` can be this node or one of its descendants.
+    /// For example, this can fill an element from the contents of a `<template>`.
+    pub fn clone_children_into(&self, destination: &NodeRef) {
+        let copies = self.children().map(|child| child.clone_subtree()).collect::<Vec<_>>();
+        destination.append_children(copies)
+    }
+
+    /// Move all children of this node, in order, before the existing children of `destination`.
+    ///
+    /// Like `move_children_to`, this moves the list of children as a whole.
+    ///
+    /// Panics if `destination` is a descendant of this node.
+    pub fn move_children_to_start(&self, destination: &NodeRef) {
+        let (first, last) = match self.take_children_for(destination) {
+            Some(children) => children,
+            None => return
+        };
+        match destination.first_child.take() {
+            Some(previous_first) => {
+                debug_assert!(previous_first.previous_sibling.is_none());
+                previous_first.previous_sibling.set(Some(Rc::downgrade(&last.0)));
+                last.next_sibling.set(Some(previous_first));
+            }
+            None => destination.last_child.set(Some(Rc::downgrade(&last.0)))
+        }
+        destination.first_child.set(Some(first.0))
+    }
+
+    /// Unlink the children of this node from it and give them a new parent,
+    /// and return the first and last of them.
+    /// Their sibling links are not changed.
+    fn take_children_for(&self, new_parent: &NodeRef) -> Option<(NodeRef, NodeRef)> {
+        if self == new_parent {
+            return None
+        }
+        assert!(!self.contains(new_parent), "cannot move children into a descendant");
+        let first = match self.first_child.take() {
+            Some(first) => NodeRef(first),
+            None => return None
+        };
+        let last = NodeRef(self.last_child.upgrade().unwrap());
+        self.last_child.set(None);
+        let mut next_child = Some(first.clone());
+        while let Some(child) = next_child {
+            child.parent.set(Some(Rc::downgrade(&new_parent.0)));
+            next_child = child.next_sibling();
+        }
+        Some((first, last))
+    }
+
+    /// Detach each child of this node for which the predicate returns `false`,
+    /// keeping the others in order, like `Vec::retain`.
+    ///
+    /// Detached children (and their descendants) that are not referenced elsewhere are dropped.
+    pub fn retain_children<F>(&self, mut f: F) where F: FnMut(&NodeRef) -> bool {
+        let mut next_child = self.first_child();
+        while let Some(child) = next_child {
+            next_child = child.next_sibling();
+            if !f(&child) {
+                child.detach()
+            }
+        }
+    }
+
+    /// Insert a replacement node at this node’s position, then detach this node.
+    ///
+    /// The replacement is detached from its previous position.
+    /// If this node has no parent, it is detached and the replacement is left where it was.
+    ///
+    /// Panics if the replacement is one of this node’s ancestors.
+    pub fn replace_with(&self, replacement: NodeRef) {
+        if replacement == *self {
+            return
+        }
+        assert!(!replacement.contains(self),
+                "replace_with() requires the replacement not to contain this node");
+        if self.parent().is_some() {
+            self.insert_before(replacement);
+        }
+        self.detach()
+    }
+
+    /// Move this node’s children into its position among its siblings, then detach this node.
+    ///
+    /// If this node has no parent, its children are detached
+    /// and become the roots of separate trees.
+    pub fn unwrap(&self) {
+        let has_parent = self.parent().is_some();
+        for child in self.children() {
+            if has_parent {
+                self.insert_before(child)
+            } else {
+                child.detach()
+            }
+        }
+        self.detach()
+    }
+
+    /// Insert a wrapper element at this node’s position, then append this node to it.
+    ///
+    /// The wrapper is detached from its previous position.
+    ///
+    /// Panics if the wrapper is not an element, or is this node or one of its ancestors.
+    pub fn wrap(&self, wrapper: NodeRef) {
+        assert!(wrapper.as_element().is_some(), "wrap() requires the wrapper to be an element");
+        assert!(!wrapper.contains(self), "wrap() requires the wrapper not to contain this node");
+        if self.parent().is_some() {
+            self.insert_before(wrapper.clone());
+        } else {
+            wrapper.detach();
+        }
+        wrapper.append(self.clone())
+    }
+
+    /// Replace this element with a new element of the given name
+    /// that takes over its attributes, children, template contents, and source position,
+    /// and return the new element.
+    ///
+    /// Element names are not changed in place since `ElementData::name` can be borrowed
+    /// without restriction (for example by selector matching).
+    /// Other references to this node keep pointing to the old element,
+    /// which is left detached and without children or template contents.
+    /// The id index of the document, if any, is invalidated.
+    ///
+    /// Panics if this node is not an element.
+    pub fn rename(&self, name: QualName) -> NodeRef {
+        let renamed = {
+            let element = self.as_element().expect("rename() requires an element");
+            let template_contents = element.template_contents.as_ref().map(|contents| {
+                let new_contents = NodeRef::new(NodeData::DocumentFragment);
+                new_contents.append_children(contents.children());
+                new_contents
+            });
+            NodeRef::new(NodeData::Element(ElementData {
+                name: name,
+                attributes: RefCell::new(element.attributes.borrow().clone()),
+                template_contents: template_contents,
+            }))
+        };
+        let document = self.document();
+        let position = self.source_position();
+        renamed.append_children(self.children());
+        self.replace_with(renamed.clone());
+        if let Some(document) = document {
+            document.invalidate_id_cache();
+            if let Some(ref mut positions) = *document._source_positions.borrow_mut() {
+                positions.remove(&(&*self.0 as *const Node as usize));
+                if let Some(position) = position {
+                    let key = &*renamed.0 as *const Node as usize;
+                    positions.insert(key, (Rc::downgrade(&renamed.0), position));
+                }
+            }
+        }
+        renamed
+    }
+
+    /// Convert the local names of elements and attributes in this subtree to ASCII lowercase,
+    /// like the HTML parser does. Namespaces and attribute values are not changed.
+    ///
+    /// If two attributes of an element get the same name, the first one is kept.
+    /// Elements with uppercase names are replaced with `rename`,
+    /// so this returns this node, or the element that replaced it,
+    /// and other references to renamed elements keep pointing to the old, detached ones.
+    /// The id index of the document, if any, is invalidated.
+    /// Template contents are not changed.
+    pub fn lowercase_names(&self) -> NodeRef {
+        let mut to_rename = Vec::new();
+        for element in self.inclusive_descendants().elements() {
+            {
+                let mut attributes = element.attributes.borrow_mut();
+                if attributes.list.iter().any(|&(ref name, _)| has_ascii_uppercase(&name.local)) {
+                    let list = ::std::mem::replace(&mut attributes.list, Vec::new());
+                    for (name, value) in list {
+                        let name = QualName::new(name.ns, Atom::from(&*name.local.to_ascii_lowercase()));
+                        if !attributes.list.iter().any(|&(ref other, _)| *other == name) {
+                            attributes.list.push((name, value))
+                        }
+                    }
+                }
+            }
+            if has_ascii_uppercase(&element.name.local) {
+                to_rename.push(element.as_node().clone())
+            }
+        }
+        let mut result = self.clone();
+        for element in to_rename {
+            let name = {
+                let name = &element.as_element().unwrap().name;
+                QualName::new(name.ns.clone(), Atom::from(&*name.local.to_ascii_lowercase()))
+            };
+            let renamed = element.rename(name);
+            if element == *self {
+                result = renamed
+            }
+        }
+        if let Some(document) = result.document() {
+            document.invalidate_id_cache()
+        }
+        result
+    }
 }
@@ -0,0 +1,126 @@
+extern crate url;
+
+use self::url::Url;
+use selectors::matching::SELECTOR_WHITESPACE;
+use string_cache::Atom;
+
+use iter::NodeIterator;
+use tree::NodeRef;
+
+impl NodeRef {
+    /// Rewrite relative URLs in the attributes of HTML elements in this subtree
+    /// to absolute URLs.
+    ///
+    /// URLs are resolved against the `href` of the first `<base>` element with one
+    /// in this subtree, itself resolved against `base`, or against `base` if there is none.
+    ///
+    /// The rewritten attributes are `href` on `<a>`, `<area>`, `<link>`, and `<base>`;
+    /// `src` on `<img>`, `<script>`, `<iframe>`, `<frame>`, `<embed>`, `<source>`,
+    /// `<track>`, `<audio>`, `<video>`, and `<input>`; `srcset` on `<img>` and `<source>`;
+    /// `poster` on `<video>`; `action` on `<form>`; `formaction` on `<button>` and `<input>`;
+    /// `cite` on `<blockquote>`, `<q>`, `<del>`, and `<ins>`; and `data` on `<object>`.
+    ///
+    /// URLs that fail to parse are left unchanged.
+    pub fn resolve_urls(&self, base: &Url) {
+        let base_element_href = self.inclusive_descendants().elements().filter_map(|element| {
+            if element.name == qualname!(html, "base") {
+                element.attributes.borrow().get(atom!("href")).map(String::from)
+            } else {
+                None
+            }
+        }).next();
+        let base = match base_element_href.and_then(|href| base.join(&href).ok()) {
+            Some(document_base) => document_base,
+            None => base.clone(),
+        };
+        for element in self.inclusive_descendants().elements() {
+            if element.name.ns != ns!(html) {
+                continue
+            }
+            let mut attributes = element.attributes.borrow_mut();
+            for &mut (ref name, ref mut value) in &mut attributes.list {
+                if name.ns != ns!() {
+                    continue
+                }
+                if is_srcset_attribute(&element.name.local, &name.local) {
+                    *value = resolve_srcset(&base, value)
+                } else if is_url_attribute(&element.name.local, &name.local) {
+                    if let Ok(url) = base.join(value) {
+                        *value = url.into_string()
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_url_attribute(element_name: &Atom, attribute_name: &Atom) -> bool {
+    match (element_name, attribute_name) {
+        (&atom!("a"), &atom!("href")) | (&atom!("area"), &atom!("href"))
+        | (&atom!("link"), &atom!("href")) | (&atom!("base"), &atom!("href"))
+        | (&atom!("img"), &atom!("src")) | (&atom!("script"), &atom!("src"))
+        | (&atom!("iframe"), &atom!("src")) | (&atom!("frame"), &atom!("src"))
+        | (&atom!("embed"), &atom!("src")) | (&atom!("source"), &atom!("src"))
+        | (&atom!("track"), &atom!("src")) | (&atom!("audio"), &atom!("src"))
+        | (&atom!("video"), &atom!("src")) | (&atom!("input"), &atom!("src"))
+        | (&atom!("video"), &atom!("poster")) | (&atom!("form"), &atom!("action"))
+        | (&atom!("button"), &atom!("formaction")) | (&atom!("input"), &atom!("formaction"))
+        | (&atom!("blockquote"), &atom!("cite")) | (&atom!("q"), &atom!("cite"))
+        | (&atom!("del"), &atom!("cite")) | (&atom!("ins"), &atom!("cite"))
+        | (&atom!("object"), &atom!("data"))
+            => true,
+        _ => false,
+    }
+}
+
+fn is_srcset_attribute(element_name: &Atom, attribute_name: &Atom) -> bool {
+    &**attribute_name == "srcset" &&
+    (*element_name == atom!("img") || *element_name == atom!("source"))
+}
+
+/// Resolve each URL of a `srcset` attribute, keeping its descriptors.
+///
+/// Each candidate is a URL followed by optional descriptors,
+/// and candidates are separated by commas.
+/// This is a simplified version of the HTML specification’s parsing algorithm:
+/// the result is normalized to `url descriptors, url descriptors`.
+fn resolve_srcset(base: &Url, srcset: &str) -> String {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_left_matches(|c| c == ',' || SELECTOR_WHITESPACE.contains(&c));
+        if rest.is_empty() {
+            break
+        }
+        let url_end = rest.find(SELECTOR_WHITESPACE).unwrap_or(rest.len());
+        let (url, after_url) = rest.split_at(url_end);
+        let (url, descriptors) = if url.ends_with(',') {
+            rest = after_url;
+            (url.trim_right_matches(','), "")
+        } else {
+            // Descriptors end at the next comma that is not in parentheses.
+            let mut depth = 0;
+            let end = after_url.char_indices().find(|&(_, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth > 0 => depth -= 1,
+                    ',' if depth == 0 => return true,
+                    _ => {}
+                }
+                false
+            }).map_or(after_url.len(), |(i, _)| i);
+            rest = &after_url[end..];
+            (url, after_url[..end].trim_matches(SELECTOR_WHITESPACE))
+        };
+        let mut candidate = match base.join(url) {
+            Ok(url) => url.into_string(),
+            Err(_) => url.to_owned(),
+        };
+        if !descriptors.is_empty() {
+            candidate.push(' ');
+            candidate.push_str(descriptors);
+        }
+        candidates.push(candidate)
+    }
+    candidates.join(", ")
+}